@@ -0,0 +1,107 @@
+//! Measures the cost of truncating a large queue one record at a time,
+//! against the previous layout as a baseline.
+//!
+//! Run with `cargo bench --bench truncate`.
+
+use std::time::{Duration, Instant};
+
+use mrecordlog::mem::MemQueue;
+
+const NUM_RECORDS: [u64; 3] = [1_000, 10_000, 50_000];
+const PAYLOAD_NUM_BYTES: usize = 256;
+
+/// The layout `MemQueue` used before: the payloads are concatenated in a single
+/// buffer, so truncating drains the buffer and rewrites the offsets of all of the
+/// remaining records.
+#[derive(Default)]
+struct ConcatenatedQueue {
+    concatenated_records: Vec<u8>,
+    start_position: u64,
+    start_offsets: Vec<usize>,
+}
+
+impl ConcatenatedQueue {
+    fn append_record(&mut self, payload: &[u8]) {
+        self.start_offsets.push(self.concatenated_records.len());
+        self.concatenated_records.extend_from_slice(payload);
+    }
+
+    fn truncate(&mut self, truncate_up_to_pos: u64) {
+        if self.start_position > truncate_up_to_pos {
+            return;
+        }
+        let first_record_to_keep = (truncate_up_to_pos + 1 - self.start_position) as usize;
+        if first_record_to_keep >= self.start_offsets.len() {
+            self.start_position += self.start_offsets.len() as u64;
+            self.concatenated_records.clear();
+            self.start_offsets.clear();
+            return;
+        }
+        let start_offset_to_keep = self.start_offsets[first_record_to_keep];
+        self.start_offsets.drain(..first_record_to_keep);
+        for start_offset in &mut self.start_offsets {
+            *start_offset -= start_offset_to_keep;
+        }
+        self.concatenated_records.drain(..start_offset_to_keep);
+        self.start_position += first_record_to_keep as u64;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.start_offsets.is_empty()
+    }
+}
+
+fn bench_concatenated_queue(num_records: u64, payload: &[u8]) -> Duration {
+    let mut queue = ConcatenatedQueue::default();
+    for _ in 0..num_records {
+        queue.append_record(payload);
+    }
+    let start = Instant::now();
+    for position in 0..num_records {
+        queue.truncate(position);
+    }
+    let elapsed = start.elapsed();
+    assert!(queue.is_empty());
+    elapsed
+}
+
+fn bench_mem_queue(num_records: u64, payload: &[u8]) -> Duration {
+    let mut mem_queue = MemQueue::default();
+    for position in 0..num_records {
+        mem_queue
+            .append_record(0.into(), 0, Some(position), None, payload)
+            .unwrap();
+    }
+    let start = Instant::now();
+    for position in 0..num_records {
+        mem_queue.truncate(position);
+    }
+    let elapsed = start.elapsed();
+    assert!(mem_queue.is_empty());
+    elapsed
+}
+
+fn report(layout: &str, num_records: u64, elapsed: Duration) {
+    println!(
+        "{layout}: truncated {num_records} records of {PAYLOAD_NUM_BYTES} bytes one at a time \
+         in {:?} ({:?} per truncation)",
+        elapsed,
+        elapsed / num_records as u32
+    );
+}
+
+fn main() {
+    let payload = vec![0u8; PAYLOAD_NUM_BYTES];
+    for num_records in NUM_RECORDS {
+        report(
+            "baseline",
+            num_records,
+            bench_concatenated_queue(num_records, &payload),
+        );
+        report(
+            "mem_queue",
+            num_records,
+            bench_mem_queue(num_records, &payload),
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Checkpoint of the in-memory queues, used to speed up the opening of the log.
+//!
+//! A checkpoint holds the queues, with their positions and the location of their records
+//! in the log, as of a given offset of the log. Upon opening, only the records written after
+//! this offset need to be replayed.
+//!
+//! The payloads are not part of the checkpoint: the records restored from it are spilled,
+//! and their payloads are read back from the log files.
+//!
+//! The checkpoint is serialized as follows, all integers being little-endian:
+//! - file number (u32) and offset (u64) of the log it was taken at,
+//! - number of queues (u32),
+//! - for each queue: name length (u16), name, start position (u64), number of records (u64),
+//!   and for each record: file number (u32), offset (u64), payload length (u32), flags (u8),
+//!   and the timestamp (u64) if the record has one,
+//! - a crc32 of all of the above (u32).
+
+use std::convert::TryInto;
+
+use crate::mem::{MemQueue, MemQueues};
+use crate::position::FileNumber;
+use crate::rolling::Directory;
+
+// Flags of a record.
+const TIMESTAMP_FLAG: u8 = 1;
+
+pub(crate) struct Checkpoint {
+    /// File and offset of the log the checkpoint was taken at.
+    pub file_number: FileNumber,
+    pub offset: u64,
+    pub mem_queues: MemQueues,
+}
+
+pub(crate) fn serialize_checkpoint(
+    file_number: FileNumber,
+    offset: u64,
+    mem_queues: &MemQueues,
+) -> Vec<u8> {
+    let mut buffer: Vec<u8> = Vec::new();
+    buffer.extend_from_slice(&u32::from(file_number).to_le_bytes());
+    buffer.extend_from_slice(&offset.to_le_bytes());
+    let queues: Vec<(&str, &MemQueue)> = mem_queues.queues().collect();
+    buffer.extend_from_slice(&(queues.len() as u32).to_le_bytes());
+    for (queue, mem_queue) in queues {
+        assert!(queue.len() <= u16::MAX as usize);
+        buffer.extend_from_slice(&(queue.len() as u16).to_le_bytes());
+        buffer.extend_from_slice(queue.as_bytes());
+        buffer.extend_from_slice(&mem_queue.start_position().to_le_bytes());
+        buffer.extend_from_slice(&(mem_queue.stats().num_records as u64).to_le_bytes());
+        for record_meta in mem_queue.record_metas() {
+            buffer.extend_from_slice(&u32::from(record_meta.file_number).to_le_bytes());
+            buffer.extend_from_slice(&record_meta.offset.to_le_bytes());
+            buffer.extend_from_slice(&(record_meta.num_bytes as u32).to_le_bytes());
+            if let Some(timestamp) = record_meta.timestamp_opt {
+                buffer.push(TIMESTAMP_FLAG);
+                buffer.extend_from_slice(&timestamp.to_le_bytes());
+            } else {
+                buffer.push(0);
+            }
+        }
+    }
+    let crc = crc32fast::hash(&buffer);
+    buffer.extend_from_slice(&crc.to_le_bytes());
+    buffer
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn read_bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn read_u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+}
+
+impl Checkpoint {
+    /// Returns None if the checkpoint is corrupted.
+    pub fn deserialize(checkpoint: &[u8]) -> Option<Checkpoint> {
+        if checkpoint.len() < 4 {
+            return None;
+        }
+        let (content, crc_bytes) = checkpoint.split_at(checkpoint.len() - 4);
+        if crc32fast::hash(content) != u32::from_le_bytes(crc_bytes.try_into().unwrap()) {
+            return None;
+        }
+        let mut cursor = Cursor(content);
+        let file_number = FileNumber::from(cursor.read_u32()?);
+        let offset = cursor.read_u64()?;
+        let num_queues = cursor.read_u32()?;
+        let mut mem_queues = MemQueues::default();
+        for _ in 0..num_queues {
+            let queue_len = cursor.read_u16()? as usize;
+            let queue = std::str::from_utf8(cursor.read_bytes(queue_len)?).ok()?;
+            let start_position = cursor.read_u64()?;
+            let num_records = cursor.read_u64()?;
+            let mut mem_queue = MemQueue::with_next_position(start_position);
+            for position in start_position..start_position + num_records {
+                let record_file_number = FileNumber::from(cursor.read_u32()?);
+                let record_offset = cursor.read_u64()?;
+                let payload_len = cursor.read_u32()? as usize;
+                let flags = cursor.read_bytes(1)?[0];
+                let timestamp_opt = match flags {
+                    0 => None,
+                    TIMESTAMP_FLAG => Some(cursor.read_u64()?),
+                    _ => return None,
+                };
+                mem_queue
+                    .append_spilled_record(
+                        record_file_number,
+                        record_offset,
+                        Some(position),
+                        timestamp_opt,
+                        payload_len,
+                    )
+                    .ok()?;
+            }
+            mem_queues.insert_queue(queue, mem_queue);
+        }
+        if !cursor.0.is_empty() {
+            return None;
+        }
+        Some(Checkpoint {
+            file_number,
+            offset,
+            mem_queues,
+        })
+    }
+
+    /// Returns true if the log still holds the file the checkpoint was taken at,
+    /// up to its offset.
+    ///
+    /// Files are removed in order, so all of the records written after the checkpoint
+    /// are then available.
+    pub fn agrees_with(&self, directory: &Directory) -> bool {
+        directory
+            .file_num_bytes(self.file_number)
+            .map(|num_bytes| num_bytes >= self.offset)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::Payload;
+
+    #[test]
+    fn test_checkpoint_serialization() {
+        let mut mem_queues = MemQueues::default();
+        mem_queues.create_queue("empty").unwrap();
+        mem_queues.touch("touched", 5).unwrap();
+        mem_queues
+            .append_record("queue", 1.into(), 10, Some(3), None, b"hello")
+            .unwrap();
+        mem_queues
+            .append_record("queue", 2.into(), 20, Some(4), Some(7), b"")
+            .unwrap();
+        let checkpoint_bytes = serialize_checkpoint(2.into(), 100, &mem_queues);
+        let checkpoint = Checkpoint::deserialize(&checkpoint_bytes).unwrap();
+        assert_eq!(checkpoint.file_number, 2.into());
+        assert_eq!(checkpoint.offset, 100);
+        let mem_queues = checkpoint.mem_queues;
+        assert_eq!(mem_queues.next_position("empty").unwrap(), 0);
+        assert_eq!(mem_queues.next_position("touched").unwrap(), 5);
+        // The payloads are not part of the checkpoint: the records are restored as spilled.
+        let locations: Vec<(u64, FileNumber, u64)> = mem_queues
+            .range_payloads("queue", ..)
+            .unwrap()
+            .map(|(position, payload)| match payload {
+                Payload::Spilled {
+                    file_number,
+                    offset,
+                } => (position, file_number, offset),
+                Payload::InMemory(_) => panic!("the record should be spilled"),
+            })
+            .collect();
+        assert_eq!(&locations, &[(3, 1.into(), 10), (4, 2.into(), 20)]);
+        assert_eq!(mem_queues.timestamp("queue", 3).unwrap(), None);
+        assert_eq!(mem_queues.timestamp("queue", 4).unwrap(), Some(7));
+        assert_eq!(mem_queues.queue_stats("queue").unwrap().num_bytes, 5);
+        assert_eq!(mem_queues.lowest_retained_file_number(), Some(1.into()));
+    }
+
+    #[test]
+    fn test_checkpoint_corrupted() {
+        let mut mem_queues = MemQueues::default();
+        mem_queues
+            .append_record("queue", 1.into(), 0, None, None, b"hello")
+            .unwrap();
+        let mut checkpoint_bytes = serialize_checkpoint(1.into(), 10, &mem_queues);
+        assert!(Checkpoint::deserialize(&checkpoint_bytes[..10]).is_none());
+        checkpoint_bytes[20] ^= 1;
+        assert!(Checkpoint::deserialize(&checkpoint_bytes).is_none());
+        assert!(Checkpoint::deserialize(&[]).is_none());
+    }
+}
@@ -0,0 +1,280 @@
+//! Compression of the payloads of the records appended to the log.
+//!
+//! The codec of each record is written along with it, so that a log written with
+//! different compression settings over time remains readable.
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+#[cfg(feature = "lz4")]
+use std::convert::TryInto;
+use std::io;
+
+/// Compression applied to the payloads of the records appended to a log.
+/// Defaults to `Compression::None`.
+///
+/// Payloads that do not shrink once compressed are written uncompressed.
+///
+/// Each codec is enabled by the feature of the same name, `lz4` or `zstd`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Zstd, at the given compression level.
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+impl Compression {
+    /// Compresses `payload` into `buffer`, and returns the codec used.
+    ///
+    /// If the codec returned is `Codec::None`, the payload is to be written as is,
+    /// and the content of `buffer` is unspecified.
+    pub fn compress(&self, payload: &[u8], buffer: &mut Vec<u8>) -> Codec {
+        buffer.clear();
+        let codec = match *self {
+            Compression::None => Codec::None,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                *buffer = lz4_flex::compress_prepend_size(payload);
+                Codec::Lz4
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => {
+                if zstd::stream::copy_encode(payload, &mut *buffer, level).is_err() {
+                    return Codec::None;
+                }
+                Codec::Zstd
+            }
+        };
+        if codec != Codec::None && buffer.len() >= payload.len() {
+            return Codec::None;
+        }
+        codec
+    }
+}
+
+/// Codec of the payload of a record, as written in the log.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Codec {
+    None = 0,
+    Lz4 = 1,
+    Zstd = 2,
+}
+
+impl TryFrom<u8> for Codec {
+    type Error = ();
+
+    fn try_from(code: u8) -> Result<Self, Self::Error> {
+        match code {
+            0 => Ok(Codec::None),
+            1 => Ok(Codec::Lz4),
+            2 => Ok(Codec::Zstd),
+            _ => Err(()),
+        }
+    }
+}
+
+// LZ4 cannot compress a payload by more than this ratio. A size prefix claiming more
+// than this is corrupted, and is rejected rather than allocated.
+#[cfg(feature = "lz4")]
+const LZ4_MAX_COMPRESSION_RATIO: usize = 255;
+
+// Zstd cannot compress a payload by more than this ratio either: at best, a block of
+// 128KiB of a single byte is encoded in 4 bytes.
+#[cfg(feature = "zstd")]
+const ZSTD_MAX_COMPRESSION_RATIO: usize = 32_768;
+
+impl Codec {
+    /// Decompresses a payload written with this codec.
+    ///
+    /// Fails with an `io::ErrorKind::Unsupported` error if the feature of the codec
+    /// is disabled.
+    pub fn decompress(self, payload: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+        match self {
+            Codec::None => Ok(Cow::Borrowed(payload)),
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => decompress_lz4(payload).map(Cow::Owned),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => decompress_zstd(payload).map(Cow::Owned),
+            #[cfg(not(feature = "lz4"))]
+            Codec::Lz4 => Err(disabled_codec_error("lz4")),
+            #[cfg(not(feature = "zstd"))]
+            Codec::Zstd => Err(disabled_codec_error("zstd")),
+        }
+    }
+}
+
+#[cfg(not(all(feature = "lz4", feature = "zstd")))]
+fn disabled_codec_error(feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "the payload is compressed with {}, whose feature is disabled",
+            feature
+        ),
+    )
+}
+
+#[cfg(feature = "lz4")]
+fn decompress_lz4(payload: &[u8]) -> io::Result<Vec<u8>> {
+    if payload.len() < 4 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the lz4 size prefix is missing",
+        ));
+    }
+    let (size_bytes, compressed_payload) = payload.split_at(4);
+    let size = u32::from_le_bytes(size_bytes.try_into().unwrap()) as usize;
+    if size
+        > compressed_payload
+            .len()
+            .saturating_mul(LZ4_MAX_COMPRESSION_RATIO)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the lz4 size prefix ({} bytes) exceeds the maximum compression ratio",
+                size
+            ),
+        ));
+    }
+    lz4_flex::decompress(compressed_payload, size)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+#[cfg(feature = "zstd")]
+fn decompress_zstd(payload: &[u8]) -> io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let max_size = payload.len().saturating_mul(ZSTD_MAX_COMPRESSION_RATIO);
+    let exceeds_max_size_error = |size: u64| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the zstd content size ({} bytes) exceeds the maximum compression ratio",
+                size
+            ),
+        )
+    };
+    // The content size written in the frame header, if any, is checked before anything
+    // is allocated. The output is bounded anyway, as the header may not hold it.
+    if let Ok(Some(size)) = zstd::zstd_safe::get_frame_content_size(payload) {
+        if size > max_size as u64 {
+            return Err(exceeds_max_size_error(size));
+        }
+    }
+    let decoder = zstd::stream::read::Decoder::with_buffer(payload)?;
+    let mut decompressed = Vec::new();
+    decoder
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() > max_size {
+        return Err(exceeds_max_size_error(decompressed.len() as u64));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn test_codec_serialize() {
+        for code in 0u8..=255u8 {
+            if let Ok(codec) = Codec::try_from(code) {
+                assert_eq!(codec as u8, code);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "lz4", feature = "zstd"))]
+    fn test_compression() {
+        let payload = b"hello hello hello hello hello hello hello hello".repeat(10);
+        let mut buffer = Vec::new();
+        for (compression, expected_codec) in [
+            (Compression::None, Codec::None),
+            (Compression::Lz4, Codec::Lz4),
+            (Compression::Zstd(3), Codec::Zstd),
+        ] {
+            let codec = compression.compress(&payload, &mut buffer);
+            assert_eq!(codec, expected_codec);
+            if codec != Codec::None {
+                assert!(buffer.len() < payload.len());
+                assert_eq!(&codec.decompress(&buffer).unwrap()[..], &payload[..]);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "lz4", feature = "zstd"))]
+    fn test_compression_incompressible_payload() {
+        let mut buffer = Vec::new();
+        assert_eq!(Compression::Lz4.compress(b"a", &mut buffer), Codec::None);
+        assert_eq!(
+            Compression::Zstd(3).compress(b"a", &mut buffer),
+            Codec::None
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "lz4", feature = "zstd"))]
+    fn test_decompress_invalid_payload() {
+        assert!(Codec::Lz4.decompress(&[16, 0, 0, 0, 255]).is_err());
+        assert!(Codec::Lz4.decompress(&[16, 0]).is_err());
+        assert!(Codec::Zstd.decompress(b"invalid").is_err());
+    }
+
+    #[test]
+    #[cfg(not(all(feature = "lz4", feature = "zstd")))]
+    fn test_decompress_disabled_codec() {
+        #[cfg(not(feature = "lz4"))]
+        assert_eq!(
+            Codec::Lz4.decompress(b"payload").unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+        #[cfg(not(feature = "zstd"))]
+        assert_eq!(
+            Codec::Zstd.decompress(b"payload").unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "lz4")]
+    fn test_decompress_lz4_oversized_size_prefix() {
+        let payload = vec![0u8; 10_000];
+        let mut buffer = Vec::new();
+        assert_eq!(Compression::Lz4.compress(&payload, &mut buffer), Codec::Lz4);
+        assert_eq!(&Codec::Lz4.decompress(&buffer).unwrap()[..], &payload[..]);
+        // A corrupted size prefix is rejected before anything is allocated.
+        buffer[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = Codec::Lz4.decompress(&buffer).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_decompress_zstd_oversized_content_size() {
+        let payload = vec![0u8; 10_000];
+        let mut buffer = Vec::new();
+        assert_eq!(
+            Compression::Zstd(3).compress(&payload, &mut buffer),
+            Codec::Zstd
+        );
+        assert_eq!(&Codec::Zstd.decompress(&buffer).unwrap()[..], &payload[..]);
+        // A frame header announcing more than the maximum compression ratio is rejected
+        // before anything is allocated.
+        let mut frame = 0xFD2FB528u32.to_le_bytes().to_vec();
+        // Single segment, with an 8 bytes content size.
+        frame.push(0xE0);
+        frame.extend_from_slice(&(1u64 << 40).to_le_bytes());
+        let error = Codec::Zstd.decompress(&frame).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -2,15 +2,30 @@ use std::io;
 
 use thiserror::Error;
 
+use crate::record::ReadRecordError;
+
 #[derive(Debug, Copy, Clone)]
 pub struct AlreadyExists;
 
+/// Returned when an operation is attempted on a log that hit an IO error
+/// leaving its files in an unknown state. The log needs to be reopened.
+#[derive(Debug, Copy, Clone)]
+pub struct Poisoned;
+
 #[derive(Error, Debug)]
 pub enum CreateQueueError {
     #[error("Already exists")]
     AlreadyExists,
     #[error("Io error: {0}")]
     IoError(#[from] io::Error),
+    #[error("Poisoned: the log needs to be reopened")]
+    Poisoned,
+}
+
+impl From<Poisoned> for CreateQueueError {
+    fn from(_: Poisoned) -> Self {
+        CreateQueueError::Poisoned
+    }
 }
 
 impl From<AlreadyExists> for CreateQueueError {
@@ -22,12 +37,42 @@ impl From<AlreadyExists> for CreateQueueError {
 #[derive(Debug)]
 pub struct TouchError;
 
+#[derive(Error, Debug)]
+pub enum DeleteQueueError {
+    #[error("Missing queue: {0}")]
+    MissingQueue(String),
+    #[error("Io error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Poisoned: the log needs to be reopened")]
+    Poisoned,
+}
+
+impl From<Poisoned> for DeleteQueueError {
+    fn from(_: Poisoned) -> Self {
+        DeleteQueueError::Poisoned
+    }
+}
+
+impl From<MissingQueue> for DeleteQueueError {
+    fn from(missing_queue: MissingQueue) -> Self {
+        DeleteQueueError::MissingQueue(missing_queue.0)
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum TruncateError {
     #[error("Missing queue: {0}")]
     MissingQueue(String),
     #[error("Io error: {0}")]
     IoError(#[from] io::Error),
+    #[error("Poisoned: the log needs to be reopened")]
+    Poisoned,
+}
+
+impl From<Poisoned> for TruncateError {
+    fn from(_: Poisoned) -> Self {
+        TruncateError::Poisoned
+    }
 }
 
 impl From<MissingQueue> for TruncateError {
@@ -36,6 +81,17 @@ impl From<MissingQueue> for TruncateError {
     }
 }
 
+/// Capacity limit that prevented a record from being appended.
+///
+/// See `CapacityLimits`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExceededLimit {
+    MemoryNumBytes,
+    QueueNumBytes,
+    NumFiles,
+    DiskUsage,
+}
+
 #[derive(Error, Debug)]
 pub enum AppendError {
     #[error("Io error: {0}")]
@@ -46,6 +102,16 @@ pub enum AppendError {
     Past,
     #[error("Future")]
     Future,
+    #[error("Poisoned: the log needs to be reopened")]
+    Poisoned,
+    #[error("Full: the {limit:?} limit was reached")]
+    Full { limit: ExceededLimit },
+}
+
+impl From<Poisoned> for AppendError {
+    fn from(_: Poisoned) -> Self {
+        AppendError::Poisoned
+    }
 }
 
 impl From<MissingQueue> for AppendError {
@@ -56,3 +122,63 @@ impl From<MissingQueue> for AppendError {
 
 #[derive(Debug)]
 pub struct MissingQueue(pub String);
+
+#[derive(Error, Debug)]
+pub enum CompactError {
+    #[error("Io error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Failed to read back a spilled record: {0}")]
+    ReadError(#[from] ReadRecordError),
+    #[error("Poisoned: the log needs to be reopened")]
+    Poisoned,
+}
+
+impl From<Poisoned> for CompactError {
+    fn from(_: Poisoned) -> Self {
+        CompactError::Poisoned
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("Io error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Poisoned: the log needs to be reopened")]
+    Poisoned,
+}
+
+impl From<Poisoned> for CheckpointError {
+    fn from(_: Poisoned) -> Self {
+        CheckpointError::Poisoned
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SubscribeError {
+    #[error("Missing queue: {0}")]
+    MissingQueue(String),
+    #[error("Truncated: the record at position {0} was removed")]
+    Truncated(u64),
+    #[error("Failed to read back a spilled record: {0}")]
+    ReadError(#[from] ReadRecordError),
+}
+
+impl From<MissingQueue> for SubscribeError {
+    fn from(missing_queue: MissingQueue) -> Self {
+        SubscribeError::MissingQueue(missing_queue.0)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RangeError {
+    #[error("Missing queue: {0}")]
+    MissingQueue(String),
+    #[error("Failed to read back a spilled record: {0}")]
+    ReadError(#[from] ReadRecordError),
+}
+
+impl From<MissingQueue> for RangeError {
+    fn from(missing_queue: MissingQueue) -> Self {
+        RangeError::MissingQueue(missing_queue.0)
+    }
+}
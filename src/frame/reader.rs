@@ -19,6 +19,10 @@ pub struct FrameReader<R> {
     available: Range<usize>,
     // The current block is corrupted.
     block_corrupted: bool,
+    // Offset in the underlying reader of the start of the available bytes.
+    offset: u64,
+    // Offset of the last frame read, or that failed to be read.
+    frame_offset: u64,
 }
 
 #[derive(Error, Debug)]
@@ -38,9 +42,42 @@ impl<R: AsyncRead + Unpin> FrameReader<R> {
             buffer: Box::new([0u8; BUFFER_LEN]),
             available: 0..0,
             block_corrupted: false,
+            offset: 0,
+            frame_offset: 0,
         }
     }
 
+    /// Opens a reader starting at `offset`.
+    ///
+    /// `reader` must already be positioned at this offset, and at the
+    /// beginning of a frame.
+    pub fn open_at_offset(reader: R, offset: u64) -> Self {
+        let block_offset = (offset % BLOCK_LEN as u64) as usize;
+        FrameReader {
+            reader,
+            buffer: Box::new([0u8; BUFFER_LEN]),
+            available: block_offset..block_offset,
+            block_corrupted: false,
+            offset,
+            frame_offset: offset,
+        }
+    }
+
+    /// Returns the underlying reader, positioned after the bytes buffered.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Returns the offset of the end of the last frame read.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the offset of the last frame read, or that failed to be read.
+    pub fn frame_offset(&self) -> u64 {
+        self.frame_offset
+    }
+
     /// Number of bytes available to read in our buffer.
     fn available_len(&self) -> usize {
         self.available.len()
@@ -128,6 +165,7 @@ impl<R: AsyncRead + Unpin> FrameReader<R> {
 
     fn advance(&mut self, num_bytes: usize) {
         self.available.start += num_bytes;
+        self.offset += num_bytes as u64;
     }
 
     // Attempt to read the header of the next frame
@@ -135,7 +173,7 @@ impl<R: AsyncRead + Unpin> FrameReader<R> {
     async fn get_frame_header(&mut self) -> Result<Header, ReadFrameError> {
         self.ensure_bytes_available(HEADER_LEN).await?;
         let header_bytes = &self.buffer[self.available.clone()][..HEADER_LEN];
-        match Header::deserialize(&header_bytes) {
+        match Header::deserialize(header_bytes) {
             Some(header) => Ok(header),
             None => {
                 self.block_corrupted = true;
@@ -147,6 +185,7 @@ impl<R: AsyncRead + Unpin> FrameReader<R> {
     // Reads the next frame.
     pub(crate) async fn read_frame(&mut self) -> Result<(FrameType, &[u8]), ReadFrameError> {
         self.go_to_next_block_if_necessary().await?;
+        self.frame_offset = self.offset;
         let header = self.get_frame_header().await?;
         let frame_num_bytes = header.len() + HEADER_LEN;
         if self.num_bytes_to_end_of_block() < frame_num_bytes {
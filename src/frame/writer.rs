@@ -21,6 +21,18 @@ impl<W: AsyncWrite + Unpin> FrameWriter<W> {
         }
     }
 
+    /// Resumes writing after the `num_bytes` bytes already written by `wrt`.
+    ///
+    /// The frames written are aligned on the blocks of these bytes.
+    pub fn resume(wrt: W, num_bytes: u64) -> Self {
+        FrameWriter {
+            wrt: BufWriter::new(wrt),
+            buffer: Box::new([0u8; BLOCK_LEN]),
+            current_block_len: (num_bytes % BLOCK_LEN as u64) as usize,
+            num_bytes_written: num_bytes,
+        }
+    }
+
     pub fn num_bytes_written(&self) -> u64 {
         self.num_bytes_written
     }
@@ -37,7 +49,7 @@ impl<W: AsyncWrite + Unpin> FrameWriter<W> {
         assert!(record_len <= BLOCK_LEN);
         let (buffer_header, buffer_record) = self.buffer[..record_len].split_at_mut(HEADER_LEN);
         buffer_record.copy_from_slice(payload);
-        Header::for_payload(frame_type, &payload).serialize(buffer_header);
+        Header::for_payload(frame_type, payload).serialize(buffer_header);
         self.current_block_len = (self.current_block_len + record_len) % BLOCK_LEN;
         self.wrt.write_all(&self.buffer[..record_len]).await?;
         self.num_bytes_written += record_len as u64;
@@ -58,6 +70,7 @@ impl<W: AsyncWrite + Unpin> FrameWriter<W> {
         let b = vec![0u8; remaining_num_bytes_in_block];
         self.wrt.write_all(&b).await?;
         self.num_bytes_written += b.len() as u64;
+        self.current_block_len = 0;
         Ok(())
     }
 
@@ -13,16 +13,31 @@
 //! level. In case of corruption, some punctual record can be lost, while
 //! later records are ok.
 
+mod checkpoint;
+pub mod compression;
 pub mod frame;
 pub mod mem;
 mod multi_record_log;
+mod options;
 pub mod position;
 pub mod record;
+mod recovery;
 pub mod rolling;
+mod shared;
+#[cfg(any(test, feature = "simulation"))]
+pub mod simulation;
+pub mod storage;
+mod writer;
 
-pub(crate) mod error;
+pub mod error;
 
 #[cfg(test)]
 mod tests;
 
+pub use compression::Compression;
 pub use multi_record_log::MultiRecordLog;
+pub use options::MultiRecordLogOptions;
+pub use recovery::{Corruption, RecoveryMode, RecoveryReport};
+pub use rolling::SyncPolicy;
+pub use shared::SharedMultiRecordLog;
+pub use writer::CapacityLimits;
@@ -1,5 +1,7 @@
 mod queue;
 mod queues;
 
-pub use self::queue::MemQueue;
+pub(crate) use self::queue::{position_for_append, Payload};
+pub use self::queue::{MemQueue, QueueStats};
+pub(crate) use self::queues::RangeRecord;
 pub use self::queues::{MemQueues, Truncation};
@@ -1,74 +1,290 @@
+use std::collections::VecDeque;
 use std::ops::{Bound, RangeBounds};
 
+use bytes::{Bytes, BytesMut};
+
 use crate::error::AppendError;
 use crate::position::FileNumber;
+use crate::record::ReadRecordError;
+use crate::rolling::PayloadReader;
+
+/// Checks the position supplied by the client against the next position of a queue.
+///
+/// Returns the position the record should be appended at, or None if the record
+/// is the last record of the queue, meaning that it was already appended.
+pub(crate) fn position_for_append(
+    next_position: u64,
+    target_position_opt: Option<u64>,
+) -> Result<Option<u64>, AppendError> {
+    let target_position = target_position_opt.unwrap_or(next_position);
+    let dist = (next_position as i64) - (target_position as i64);
+    match dist {
+        i64::MIN..=-1 => Err(AppendError::Future),
+        // Happy path. This record is a new record.
+        0 => Ok(Some(target_position)),
+        // This record was already added.
+        1 => Ok(None),
+        2.. => Err(AppendError::Past),
+    }
+}
+
+/// Statistics about the records held by a queue.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct QueueStats {
+    pub num_records: usize,
+    /// Number of bytes of the payloads of the records.
+    pub num_bytes: usize,
+    pub first_position: Option<u64>,
+    pub last_position: Option<u64>,
+}
+
+// Bounds of the number of bytes of the chunks the payloads are written to.
+// Chunks grow with the number of bytes held in memory by the queue.
+const MIN_CHUNK_NUM_BYTES: usize = 4_096;
+const MAX_CHUNK_NUM_BYTES: usize = 1 << 20;
+
+pub(crate) struct RecordMeta {
+    // None once the record is spilled. Its payload is then read back from its file.
+    pub payload_opt: Option<Bytes>,
+    pub num_bytes: usize,
+    pub file_number: FileNumber,
+    // Offset of the record in its file.
+    pub offset: u64,
+    pub timestamp_opt: Option<u64>,
+}
+
+impl RecordMeta {
+    /// Returns the payload of the record, which must be held in memory.
+    pub fn in_memory_payload(&self) -> &Bytes {
+        self.payload_opt
+            .as_ref()
+            .expect("the record should be held in memory: spilled records are read by streams")
+    }
 
-#[derive(Clone, Copy)]
-struct RecordMeta {
-    start_offset: usize,
-    file_number: FileNumber,
+    /// Returns the payload of the record, without reading it back if it was spilled.
+    pub fn payload(&self) -> Payload {
+        match &self.payload_opt {
+            Some(payload) => Payload::InMemory(payload.clone()),
+            None => Payload::Spilled {
+                file_number: self.file_number,
+                offset: self.offset,
+            },
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct MemQueue {
-    // Concatenated records
-    concatenated_records: Vec<u8>,
+    // Remaining capacity of the chunk being written to.
+    // The payloads are split from it, so that they share the allocation of the chunk,
+    // and can be handed out without copying them.
+    chunk: BytesMut,
     start_position: u64,
-    record_metas: Vec<RecordMeta>,
+    // Truncating pops the records from the front, and frees a chunk
+    // once all of its payloads are dropped.
+    record_metas: VecDeque<RecordMeta>,
+    // Number of bytes of the payloads of the records.
+    num_bytes: usize,
+    // The records spilled are always the first records of the queue.
+    num_spilled_records: usize,
+    num_spilled_bytes: usize,
+    // Tells the queue apart from the deleted queues of the same name.
+    incarnation: u64,
 }
 
 impl MemQueue {
     pub fn with_next_position(next_position: u64) -> Self {
         MemQueue {
-            concatenated_records: Vec::new(),
             start_position: next_position,
-            record_metas: Vec::new(),
+            ..Default::default()
         }
     }
+
+    pub(crate) fn incarnation(&self) -> u64 {
+        self.incarnation
+    }
+
+    pub(crate) fn set_incarnation(&mut self, incarnation: u64) {
+        self.incarnation = incarnation;
+    }
+
     pub fn first_retained_position(&self) -> Option<FileNumber> {
-        Some(self.record_metas.first()?.file_number)
+        Some(self.record_metas.front()?.file_number)
     }
 
     pub fn is_empty(&self) -> bool {
         self.record_metas.is_empty()
     }
 
+    /// Returns the number of bytes allocated to hold the records of the queue.
+    ///
+    /// A chunk is only freed once all of its payloads are dropped, including the ones
+    /// returned by `range_bytes`, so the memory actually allocated may be larger.
+    ///
+    /// The payloads of the records spilled are not accounted for.
+    pub fn memory_usage(&self) -> usize {
+        self.in_memory_num_bytes()
+            + self.chunk.capacity()
+            + self.record_metas.capacity() * std::mem::size_of::<RecordMeta>()
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let num_records = self.record_metas.len();
+        let (first_position, last_position) = if self.is_empty() {
+            (None, None)
+        } else {
+            (Some(self.start_position), Some(self.next_position() - 1))
+        };
+        QueueStats {
+            num_records,
+            num_bytes: self.num_bytes,
+            first_position,
+            last_position,
+        }
+    }
+
+    /// Returns the position of the first record held in the queue, or the next
+    /// position if the queue is empty.
+    pub fn start_position(&self) -> u64 {
+        self.start_position
+    }
+
     /// Returns what should be the next position.
     pub fn next_position(&self) -> u64 {
         self.start_position + self.record_metas.len() as u64
     }
 
-    /// Returns true iff the record was effectively added.
-    /// False if the record was added in the previous call.
+    // A queue that never received any record accepts a first record at any position.
+    fn is_pristine(&self) -> bool {
+        self.start_position == u64::default() && self.record_metas.is_empty()
+    }
+
+    /// Returns the position the record should be appended at, without appending it.
+    ///
+    /// See `append_record`.
+    pub fn position_for_append(
+        &self,
+        target_position_opt: Option<u64>,
+    ) -> Result<Option<u64>, AppendError> {
+        if self.is_pristine() {
+            return Ok(Some(target_position_opt.unwrap_or_default()));
+        }
+        position_for_append(self.next_position(), target_position_opt)
+    }
+
+    /// Returns the number of bytes of the payloads held in memory, that is of the records
+    /// that were not spilled.
+    pub fn in_memory_num_bytes(&self) -> usize {
+        self.num_bytes - self.num_spilled_bytes
+    }
+
+    /// Returns the position the record should be appended at, and makes it the first
+    /// position of the queue if the queue is pristine.
+    fn position_for_new_record(
+        &mut self,
+        target_position_opt: Option<u64>,
+    ) -> Result<Option<u64>, AppendError> {
+        let position_opt = self.position_for_append(target_position_opt)?;
+        if let Some(position) = position_opt {
+            if self.is_pristine() {
+                self.start_position = position;
+            }
+        }
+        Ok(position_opt)
+    }
+
+    /// Returns the position of the record if it was effectively added.
+    /// None if the record was added in the previous call.
+    ///
+    /// `file_number` and `offset` locate the record in the log, so that it can be
+    /// read back once spilled.
     ///
     /// AppendError if the record is strangely in the past or is too much in the future.
     pub fn append_record(
         &mut self,
         file_number: FileNumber,
+        offset: u64,
         target_position_opt: Option<u64>,
+        timestamp_opt: Option<u64>,
         payload: &[u8],
     ) -> Result<Option<u64>, AppendError> {
-        let target_position = target_position_opt.unwrap_or_else(|| self.next_position());
-        if self.start_position == u64::default() && self.record_metas.is_empty() {
-            self.start_position = target_position;
-        }
-        let dist = (self.next_position() as i64) - (target_position as i64);
-        match dist {
-            i64::MIN..=-1 => Err(AppendError::Future),
-            // Happy path. This record is a new record.
-            0 => {
-                let record_meta = RecordMeta {
-                    start_offset: self.concatenated_records.len(),
-                    file_number,
-                };
-                self.record_metas.push(record_meta);
-                self.concatenated_records.extend_from_slice(payload);
-                Ok(Some(target_position))
-            }
-            // This record was already added.
-            1 => Ok(None),
-            2.. => Err(AppendError::Past),
+        let position = if let Some(position) = self.position_for_new_record(target_position_opt)? {
+            position
+        } else {
+            return Ok(None);
+        };
+        if self.chunk.capacity() < payload.len() {
+            let chunk_num_bytes = self
+                .in_memory_num_bytes()
+                .clamp(MIN_CHUNK_NUM_BYTES, MAX_CHUNK_NUM_BYTES);
+            self.chunk.reserve(payload.len().max(chunk_num_bytes));
         }
+        self.chunk.extend_from_slice(payload);
+        let record_meta = RecordMeta {
+            payload_opt: Some(self.chunk.split().freeze()),
+            num_bytes: payload.len(),
+            file_number,
+            offset,
+            timestamp_opt,
+        };
+        self.record_metas.push_back(record_meta);
+        self.num_bytes += payload.len();
+        Ok(Some(position))
+    }
+
+    /// Appends a record that is already spilled, holding `num_bytes` bytes.
+    ///
+    /// All of the records of the queue must be spilled already.
+    pub(crate) fn append_spilled_record(
+        &mut self,
+        file_number: FileNumber,
+        offset: u64,
+        target_position_opt: Option<u64>,
+        timestamp_opt: Option<u64>,
+        num_bytes: usize,
+    ) -> Result<Option<u64>, AppendError> {
+        assert_eq!(self.num_spilled_records, self.record_metas.len());
+        let position = if let Some(position) = self.position_for_new_record(target_position_opt)? {
+            position
+        } else {
+            return Ok(None);
+        };
+        let record_meta = RecordMeta {
+            payload_opt: None,
+            num_bytes,
+            file_number,
+            offset,
+            timestamp_opt,
+        };
+        self.record_metas.push_back(record_meta);
+        self.num_bytes += num_bytes;
+        self.num_spilled_records += 1;
+        self.num_spilled_bytes += num_bytes;
+        Ok(Some(position))
+    }
+
+    /// Returns true if some of the records of the queue were spilled.
+    pub(crate) fn has_spilled_records(&self) -> bool {
+        self.num_spilled_records > 0
+    }
+
+    /// Returns the file and the offset of the oldest record held in memory.
+    pub(crate) fn oldest_in_memory_record(&self) -> Option<(FileNumber, u64)> {
+        let record_meta = self.record_metas.get(self.num_spilled_records)?;
+        Some((record_meta.file_number, record_meta.offset))
+    }
+
+    /// Drops the payload of the oldest record held in memory. It is read back from
+    /// its file when needed.
+    ///
+    /// Returns the number of bytes of the payload.
+    pub(crate) fn spill_oldest_record(&mut self) -> usize {
+        let record_meta = &mut self.record_metas[self.num_spilled_records];
+        record_meta.payload_opt = None;
+        let num_bytes = record_meta.num_bytes;
+        self.num_spilled_records += 1;
+        self.num_spilled_bytes += num_bytes;
+        num_bytes
     }
 
     fn position_to_idx(&self, position: u64) -> Option<usize> {
@@ -79,11 +295,38 @@ impl MemQueue {
         if idx > self.record_metas.len() {
             return None;
         }
-        Some(idx as usize)
+        Some(idx)
+    }
+
+    /// Returns the timestamp of the record at `position`, if it has one.
+    pub fn timestamp(&self, position: u64) -> Option<u64> {
+        let idx = position.checked_sub(self.start_position)? as usize;
+        self.record_metas.get(idx)?.timestamp_opt
     }
 
-    pub fn range<'a, R>(&'a self, range: R) -> impl Iterator<Item = (u64, &'a [u8])> + 'a
-    where R: RangeBounds<u64> + 'static {
+    /// Returns the position of the first record with a timestamp greater or equal
+    /// to `timestamp`, or the next position if there is none.
+    ///
+    /// The timestamps of a queue are expected to be non-decreasing. Records without
+    /// timestamp are considered older than any timestamp.
+    pub fn position_for_timestamp(&self, timestamp: u64) -> u64 {
+        let idx = self.record_metas.partition_point(|record_meta| {
+            record_meta
+                .timestamp_opt
+                .map(|record_timestamp| record_timestamp < timestamp)
+                .unwrap_or(true)
+        });
+        self.start_position + idx as u64
+    }
+
+    /// Returns the records of the queue within `range`, spilled or not.
+    pub(crate) fn range_record_metas<'a, R>(
+        &'a self,
+        range: R,
+    ) -> impl Iterator<Item = (u64, &'a RecordMeta)> + 'a
+    where
+        R: RangeBounds<u64> + 'static,
+    {
         let start_idx: usize = match range.start_bound() {
             Bound::Included(&start_from) => self
                 .position_to_idx(start_from)
@@ -95,24 +338,18 @@ impl MemQueue {
             }
             Bound::Unbounded => 0,
         };
-        (start_idx..self.record_metas.len())
-            .take_while(move |&idx| {
-                let position = self.start_position + idx as u64;
-                range.contains(&position)
-            })
-            .map(move |idx| {
-                let position = self.start_position + idx as u64;
-                let start_offset = self.record_metas[idx].start_offset;
-                if let Some(next_record_meta) = self.record_metas.get(idx + 1) {
-                    let end_offset = next_record_meta.start_offset;
-                    (
-                        position,
-                        &self.concatenated_records[start_offset..end_offset],
-                    )
-                } else {
-                    (position, &self.concatenated_records[start_offset..])
-                }
-            })
+        let start_idx = start_idx.min(self.record_metas.len());
+        let start_position = self.start_position;
+        self.record_metas
+            .range(start_idx..)
+            .zip(start_position + start_idx as u64..)
+            .take_while(move |(_, position)| range.contains(position))
+            .map(|(record_meta, position)| (position, record_meta))
+    }
+
+    /// Returns the records of the queue, spilled or not.
+    pub(crate) fn record_metas(&self) -> impl Iterator<Item = &RecordMeta> {
+        self.record_metas.iter()
     }
 
     /// Removes all records coming before position,
@@ -121,22 +358,60 @@ impl MemQueue {
         if self.start_position > truncate_up_to_pos {
             return;
         }
-        let first_record_to_keep =
-            if let Some(first_record_to_keep) = self.position_to_idx(truncate_up_to_pos + 1) {
+        let first_record_to_keep = match self.position_to_idx(truncate_up_to_pos + 1) {
+            Some(first_record_to_keep) if first_record_to_keep < self.record_metas.len() => {
                 first_record_to_keep
-            } else {
+            }
+            _ => {
                 // clear the queue.
-                self.start_position = self.start_position + self.record_metas.len() as u64;
-                self.concatenated_records.clear();
+                self.start_position += self.record_metas.len() as u64;
                 self.record_metas.clear();
+                self.num_bytes = 0;
+                self.num_spilled_records = 0;
+                self.num_spilled_bytes = 0;
                 return;
-            };
-        let start_offset_to_keep: usize = self.record_metas[first_record_to_keep].start_offset;
-        self.record_metas.drain(..first_record_to_keep);
-        for record_meta in &mut self.record_metas {
-            record_meta.start_offset -= start_offset_to_keep;
+            }
+        };
+        for record_meta in self.record_metas.drain(..first_record_to_keep) {
+            self.num_bytes -= record_meta.num_bytes;
+            if record_meta.payload_opt.is_none() {
+                self.num_spilled_records -= 1;
+                self.num_spilled_bytes -= record_meta.num_bytes;
+            }
+        }
+        self.start_position += first_record_to_keep as u64;
+    }
+}
+
+/// Payload of a record, as held by a queue.
+#[derive(Clone, Debug)]
+pub(crate) enum Payload {
+    InMemory(Bytes),
+    /// The record was spilled, and its payload is read back from its file.
+    Spilled {
+        file_number: FileNumber,
+        offset: u64,
+    },
+}
+
+impl Payload {
+    /// Returns the payload, reading it back from the log files if the record was spilled.
+    pub async fn read(
+        self,
+        position: u64,
+        payload_reader: &mut PayloadReader,
+    ) -> Result<Bytes, ReadRecordError> {
+        match self {
+            Payload::InMemory(payload) => Ok(payload),
+            Payload::Spilled {
+                file_number,
+                offset,
+            } => {
+                let payload = payload_reader
+                    .read_payload(file_number, offset, position)
+                    .await?;
+                Ok(Bytes::from(payload))
+            }
         }
-        self.concatenated_records.drain(..start_offset_to_keep);
-        self.start_position = self.start_position + first_record_to_keep as u64;
     }
 }
@@ -1,15 +1,41 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
 use std::ops::{RangeBounds, RangeTo};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures_util::stream::{self, Stream};
 
 use crate::error::{AlreadyExists, AppendError, MissingQueue, TouchError};
-use crate::mem::MemQueue;
+use crate::mem::{MemQueue, Payload, QueueStats};
 use crate::position::FileNumber;
+use crate::record::ReadRecordError;
+use crate::rolling::PayloadReader;
+use crate::storage::Storage;
+
+/// Record read from a queue, with its position. Its payload is borrowed from the queue,
+/// unless the record was spilled.
+pub(crate) type RangeRecord<'a> = Result<(u64, Cow<'a, [u8]>), ReadRecordError>;
 
 #[derive(Default)]
 pub struct MemQueues {
     queues: HashMap<String, MemQueue>,
     // Range of records currently being held in all queues.
     lowest_retained_file_number: Option<FileNumber>,
+    // Storage of the log files the records spilled are read back from.
+    storage_opt: Option<Arc<dyn Storage>>,
+    // The oldest records are spilled once the payloads held in memory exceed this
+    // number of bytes.
+    spill_num_bytes_opt: Option<usize>,
+    // Number of bytes of the payloads of the records of all queues, spilled or not.
+    num_bytes: usize,
+    // Number of bytes of the payloads held in memory, across all queues.
+    in_memory_num_bytes: usize,
+    // Queues holding records in memory, ordered by the file and the offset of their
+    // oldest record held in memory, so that the oldest records are spilled first.
+    spill_order: BTreeSet<((FileNumber, u64), String)>,
+    // Incarnation of the last queue created.
+    last_incarnation: u64,
 }
 
 impl MemQueues {
@@ -17,10 +43,74 @@ impl MemQueues {
         if self.queues.contains_key(queue) {
             return Err(AlreadyExists);
         }
-        self.queues.insert(queue.to_string(), MemQueue::default());
+        self.insert_mem_queue(queue, MemQueue::default());
         Ok(())
     }
 
+    /// Inserts `mem_queue`, replacing the queue of the same name if any.
+    ///
+    /// A queue replacing another one keeps its incarnation, otherwise it gets a new one.
+    fn insert_mem_queue(&mut self, queue: &str, mut mem_queue: MemQueue) {
+        let incarnation = if let Some(previous_mem_queue) = self.remove_mem_queue(queue) {
+            previous_mem_queue.incarnation()
+        } else {
+            self.last_incarnation += 1;
+            self.last_incarnation
+        };
+        mem_queue.set_incarnation(incarnation);
+        self.num_bytes += mem_queue.stats().num_bytes;
+        self.in_memory_num_bytes += mem_queue.in_memory_num_bytes();
+        if let Some(oldest_in_memory_record) = mem_queue.oldest_in_memory_record() {
+            self.spill_order
+                .insert((oldest_in_memory_record, queue.to_string()));
+        }
+        self.queues.insert(queue.to_string(), mem_queue);
+    }
+
+    fn remove_mem_queue(&mut self, queue: &str) -> Option<MemQueue> {
+        let mem_queue = self.queues.remove(queue)?;
+        self.num_bytes -= mem_queue.stats().num_bytes;
+        self.in_memory_num_bytes -= mem_queue.in_memory_num_bytes();
+        if let Some(oldest_in_memory_record) = mem_queue.oldest_in_memory_record() {
+            self.spill_order
+                .remove(&(oldest_in_memory_record, queue.to_string()));
+        }
+        Some(mem_queue)
+    }
+
+    /// Applies `update_fn` to `queue`, keeping the number of bytes of the queues
+    /// and the spill order up to date.
+    ///
+    /// Returns None if the queue does not exist.
+    fn update_mem_queue<T>(
+        &mut self,
+        queue: &str,
+        update_fn: impl FnOnce(&mut MemQueue) -> T,
+    ) -> Option<T> {
+        let mem_queue = self.queues.get_mut(queue)?;
+        let previous_num_bytes = mem_queue.stats().num_bytes;
+        let previous_in_memory_num_bytes = mem_queue.in_memory_num_bytes();
+        let previous_oldest_in_memory_record = mem_queue.oldest_in_memory_record();
+        let update_res = update_fn(mem_queue);
+        self.num_bytes = self.num_bytes - previous_num_bytes + mem_queue.stats().num_bytes;
+        self.in_memory_num_bytes = self.in_memory_num_bytes - previous_in_memory_num_bytes
+            + mem_queue.in_memory_num_bytes();
+        let oldest_in_memory_record = mem_queue.oldest_in_memory_record();
+        // Appending a record to a queue already holding records in memory, the most
+        // common update, leaves the spill order untouched.
+        if oldest_in_memory_record != previous_oldest_in_memory_record {
+            if let Some(previous_oldest_in_memory_record) = previous_oldest_in_memory_record {
+                self.spill_order
+                    .remove(&(previous_oldest_in_memory_record, queue.to_string()));
+            }
+            if let Some(oldest_in_memory_record) = oldest_in_memory_record {
+                self.spill_order
+                    .insert((oldest_in_memory_record, queue.to_string()));
+            }
+        }
+        Some(update_res)
+    }
+
     pub fn empty_queue_positions<'a>(&'a self) -> impl Iterator<Item = (&'a str, u64)> + 'a {
         self.queues.iter().filter_map(|(queue, mem_queue)| {
             if mem_queue.is_empty() {
@@ -39,17 +129,91 @@ impl MemQueues {
             .ok_or_else(|| MissingQueue(queue.to_string()))
     }
 
-    pub fn contains_queue(&mut self, queue: &str) -> bool {
+    pub fn contains_queue(&self, queue: &str) -> bool {
         self.queues.contains_key(queue)
     }
 
-    fn get_or_create_queue_mut(&mut self, queue: &str) -> &mut MemQueue {
-        // We do not rely on `entry` in order to avoid
-        // the allocation.
-        if !self.queues.contains_key(queue) {
-            self.queues.insert(queue.to_string(), MemQueue::default());
+    /// Returns the position a record should be appended at, without appending it.
+    ///
+    /// See `append_record`.
+    pub fn position_for_append(
+        &self,
+        queue: &str,
+        position_opt: Option<u64>,
+    ) -> Result<Option<u64>, AppendError> {
+        self.get_queue(queue)?.position_for_append(position_opt)
+    }
+
+    /// Returns the number of bytes allocated to hold the records of all queues.
+    pub fn memory_usage(&self) -> usize {
+        self.queues.values().map(MemQueue::memory_usage).sum()
+    }
+
+    /// Makes the queues spill their oldest records once the payloads held in memory exceed
+    /// `spill_num_bytes_opt`, if any.
+    ///
+    /// The records spilled, including the ones restored from a checkpoint, are read back
+    /// from the log files of `storage`.
+    pub(crate) fn set_spill(
+        &mut self,
+        storage: Arc<dyn Storage>,
+        spill_num_bytes_opt: Option<usize>,
+    ) {
+        self.storage_opt = Some(storage);
+        self.spill_num_bytes_opt = spill_num_bytes_opt;
+        self.spill_if_needed();
+    }
+
+    // Spills the oldest records held in memory, across all queues, until
+    // the payloads held in memory fit in the limit.
+    fn spill_if_needed(&mut self) {
+        let spill_num_bytes = if let Some(spill_num_bytes) = self.spill_num_bytes_opt {
+            spill_num_bytes
+        } else {
+            return;
+        };
+        while self.in_memory_num_bytes > spill_num_bytes {
+            let (_, queue) = if let Some(oldest) = self.spill_order.pop_first() {
+                oldest
+            } else {
+                break;
+            };
+            let mem_queue = self
+                .queues
+                .get_mut(&queue)
+                .expect("the queues of the spill order should exist");
+            self.in_memory_num_bytes -= mem_queue.spill_oldest_record();
+            if let Some(oldest_in_memory_record) = mem_queue.oldest_in_memory_record() {
+                self.spill_order.insert((oldest_in_memory_record, queue));
+            }
         }
-        self.queues.get_mut(queue).unwrap()
+    }
+
+    /// Returns the number of bytes of the payloads of the records of all queues.
+    ///
+    /// The records spilled are accounted for.
+    pub fn num_bytes(&self) -> usize {
+        self.num_bytes
+    }
+
+    pub fn queue_stats(&self, queue: &str) -> Result<QueueStats, MissingQueue> {
+        Ok(self.get_queue(queue)?.stats())
+    }
+
+    /// Returns the incarnation of `queue`, which changes if the queue is deleted and
+    /// created again.
+    pub(crate) fn incarnation(&self, queue: &str) -> Result<u64, MissingQueue> {
+        Ok(self.get_queue(queue)?.incarnation())
+    }
+
+    /// Returns the position of the first record of `queue` that was not truncated.
+    pub fn start_position(&self, queue: &str) -> Result<u64, MissingQueue> {
+        Ok(self.get_queue(queue)?.start_position())
+    }
+
+    /// Returns the position that will be assigned to the next record appended to `queue`.
+    pub fn next_position(&self, queue: &str) -> Result<u64, MissingQueue> {
+        Ok(self.get_queue(queue)?.next_position())
     }
 
     pub fn touch(&mut self, queue: &str, start_position: u64) -> Result<(), TouchError> {
@@ -61,10 +225,7 @@ impl MemQueues {
                 Err(TouchError)
             }
         } else {
-            self.queues.insert(
-                queue.to_string(),
-                MemQueue::with_next_position(start_position),
-            );
+            self.insert_mem_queue(queue, MemQueue::with_next_position(start_position));
             Ok(())
         }
     }
@@ -83,32 +244,193 @@ impl MemQueues {
     ///
     /// If no local_position is supplied, the new record position will be
     /// 1 more than the last position.
+    ///
+    /// `file_number` and `offset` locate the record in the log.
     pub(crate) fn append_record(
         &mut self,
         queue: &str,
         file_number: FileNumber,
+        offset: u64,
         position_opt: Option<u64>,
+        timestamp_opt: Option<u64>,
         record: &[u8],
     ) -> Result<Option<u64>, AppendError> {
-        let res =
-            self.get_or_create_queue_mut(queue)
-                .append_record(file_number, position_opt, record)?;
+        // We do not rely on `entry` in order to avoid
+        // the allocation.
+        if !self.queues.contains_key(queue) {
+            self.insert_mem_queue(queue, MemQueue::default());
+        }
+        let res = self
+            .update_mem_queue(queue, |mem_queue| {
+                mem_queue.append_record(file_number, offset, position_opt, timestamp_opt, record)
+            })
+            .expect("the queue should exist")?;
         if self.lowest_retained_file_number.is_none() {
             self.lowest_retained_file_number = Some(file_number);
         }
+        self.spill_if_needed();
         Ok(res)
     }
 
+    /// Returns the timestamp of the record of `queue` at `position`, if it has one.
+    pub fn timestamp(&self, queue: &str, position: u64) -> Result<Option<u64>, MissingQueue> {
+        Ok(self.get_queue(queue)?.timestamp(position))
+    }
+
+    /// Returns the position of the first record of `queue` with a timestamp greater
+    /// or equal to `timestamp`, or its next position if there is none.
+    ///
+    /// See `MemQueue::position_for_timestamp`.
+    pub fn position_for_timestamp(&self, queue: &str, timestamp: u64) -> Result<u64, MissingQueue> {
+        Ok(self.get_queue(queue)?.position_for_timestamp(timestamp))
+    }
+
     /// Returns the first record with position greater of equal to position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range holds records spilled. See `range_stream`.
     pub(crate) fn range<'a, R>(
         &'a self,
         queue: &str,
         position_range: R,
-    ) -> Result<impl Iterator<Item = (u64, &'a [u8])> + 'a, crate::error::MissingQueue>
+    ) -> Result<impl Iterator<Item = (u64, &'a [u8])> + 'a, MissingQueue>
     where
         R: RangeBounds<u64> + 'static,
     {
-        Ok(self.get_queue(queue)?.range(position_range))
+        let record_metas = self.get_queue(queue)?.range_record_metas(position_range);
+        Ok(record_metas
+            .map(|(position, record_meta)| (position, &record_meta.in_memory_payload()[..])))
+    }
+
+    /// Same as `range`, but returns payloads that do not borrow the queues.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range holds records spilled. See `range_bytes_stream`.
+    pub(crate) fn range_bytes<'a, R>(
+        &'a self,
+        queue: &str,
+        position_range: R,
+    ) -> Result<impl Iterator<Item = (u64, Bytes)> + 'a, MissingQueue>
+    where
+        R: RangeBounds<u64> + 'static,
+    {
+        let record_metas = self.get_queue(queue)?.range_record_metas(position_range);
+        Ok(record_metas
+            .map(|(position, record_meta)| (position, record_meta.in_memory_payload().clone())))
+    }
+
+    /// Same as `range`, but the payloads of the records spilled are read back from
+    /// the log files.
+    ///
+    /// The payloads of the records held in memory are borrowed.
+    pub(crate) fn range_stream<'a, R>(
+        &'a self,
+        queue: &str,
+        position_range: R,
+    ) -> Result<impl Stream<Item = RangeRecord<'a>> + 'a, MissingQueue>
+    where
+        R: RangeBounds<u64> + 'static,
+    {
+        let record_metas = self.get_queue(queue)?.range_record_metas(position_range);
+        let records = stream::unfold(
+            (record_metas, self.payload_reader()),
+            |(mut record_metas, mut payload_reader)| async move {
+                let (position, record_meta) = record_metas.next()?;
+                let record_res = match &record_meta.payload_opt {
+                    Some(payload) => Ok((position, Cow::Borrowed(&payload[..]))),
+                    None => payload_reader
+                        .read_payload(record_meta.file_number, record_meta.offset, position)
+                        .await
+                        .map(|payload| (position, Cow::Owned(payload))),
+                };
+                Some((record_res, (record_metas, payload_reader)))
+            },
+        );
+        Ok(records)
+    }
+
+    /// Same as `range_bytes`, but the payloads of the records spilled are read back from
+    /// the log files.
+    pub(crate) fn range_bytes_stream<'a, R>(
+        &'a self,
+        queue: &str,
+        position_range: R,
+    ) -> Result<impl Stream<Item = Result<(u64, Bytes), ReadRecordError>> + 'a, MissingQueue>
+    where
+        R: RangeBounds<u64> + 'static,
+    {
+        let payloads = self.range_payloads(queue, position_range)?;
+        let records = stream::unfold(
+            (payloads, self.payload_reader()),
+            |(mut payloads, mut payload_reader)| async move {
+                let (position, payload) = payloads.next()?;
+                let record_res = payload
+                    .read(position, &mut payload_reader)
+                    .await
+                    .map(|payload| (position, payload));
+                Some((record_res, (payloads, payload_reader)))
+            },
+        );
+        Ok(records)
+    }
+
+    /// Returns the payloads of the records of `queue` within `position_range`,
+    /// without reading back the records spilled.
+    ///
+    /// This makes it possible to collect the records while holding a lock over the
+    /// queues, and to read the records spilled once it is released.
+    pub(crate) fn range_payloads<'a, R>(
+        &'a self,
+        queue: &str,
+        position_range: R,
+    ) -> Result<impl Iterator<Item = (u64, Payload)> + 'a, MissingQueue>
+    where
+        R: RangeBounds<u64> + 'static,
+    {
+        let record_metas = self.get_queue(queue)?.range_record_metas(position_range);
+        Ok(record_metas.map(|(position, record_meta)| (position, record_meta.payload())))
+    }
+
+    /// Returns a reader of the payloads of the records spilled.
+    pub(crate) fn payload_reader(&self) -> PayloadReader {
+        PayloadReader::new(self.storage_opt.clone())
+    }
+
+    /// Reads back the payloads of all of the records spilled, and holds them in memory.
+    ///
+    /// This is used when spilling is disabled, to restore the records of a checkpoint,
+    /// which are all spilled.
+    pub(crate) async fn load_spilled_records(&mut self) -> Result<(), ReadRecordError> {
+        let mut payload_reader = self.payload_reader();
+        let mut loaded_queues: Vec<(String, MemQueue)> = Vec::new();
+        for (queue, mem_queue) in &self.queues {
+            if !mem_queue.has_spilled_records() {
+                continue;
+            }
+            let mut loaded_queue = MemQueue::with_next_position(mem_queue.start_position());
+            for (position, record_meta) in mem_queue.range_record_metas(..) {
+                let payload = record_meta
+                    .payload()
+                    .read(position, &mut payload_reader)
+                    .await?;
+                loaded_queue
+                    .append_record(
+                        record_meta.file_number,
+                        record_meta.offset,
+                        Some(position),
+                        record_meta.timestamp_opt,
+                        &payload,
+                    )
+                    .expect("the records of the queue should be contiguous");
+            }
+            loaded_queues.push((queue.clone(), loaded_queue));
+        }
+        for (queue, loaded_queue) in loaded_queues {
+            self.insert_mem_queue(&queue, loaded_queue);
+        }
+        Ok(())
     }
 
     /// Removes records up to the supplied `position`,
@@ -127,7 +449,114 @@ impl MemQueues {
                 // There are no file to remove anyway.
                 return Truncation::NoTruncation;
             };
-        self.get_or_create_queue_mut(queue).truncate(position);
+        if self
+            .update_mem_queue(queue, |mem_queue| mem_queue.truncate(position))
+            .is_none()
+        {
+            // When replaying the log, the queue is unknown if the files holding its creation
+            // were removed. Its position is then restored by the records following.
+            return Truncation::NoTruncation;
+        }
+        self.update_lowest_retained_file_number(previous_lowest_retained_file_number)
+    }
+
+    /// Removes `queue` and all of its records.
+    ///
+    /// If one or more files should be removed,
+    /// returns the range of the files that should be removed
+    pub fn delete_queue(&mut self, queue: &str) -> Result<Truncation, MissingQueue> {
+        if self.remove_mem_queue(queue).is_none() {
+            return Err(MissingQueue(queue.to_string()));
+        }
+        let previous_lowest_retained_file_number =
+            if let Some(file_number) = self.lowest_retained_file_number {
+                file_number
+            } else {
+                // There are no file to remove anyway.
+                return Ok(Truncation::NoTruncation);
+            };
+        Ok(self.update_lowest_retained_file_number(previous_lowest_retained_file_number))
+    }
+
+    /// Returns the queues holding records in files older than `file_number`.
+    pub fn queues_retaining_files_before(&self, file_number: FileNumber) -> Vec<String> {
+        self.queues
+            .iter()
+            .filter(|(_, mem_queue)| {
+                mem_queue
+                    .first_retained_position()
+                    .map(|first_retained_file_number| first_retained_file_number < file_number)
+                    .unwrap_or(false)
+            })
+            .map(|(queue, _)| queue.clone())
+            .collect()
+    }
+
+    pub(crate) fn queues(&self) -> impl Iterator<Item = (&str, &MemQueue)> {
+        self.queues
+            .iter()
+            .map(|(queue, mem_queue)| (queue.as_str(), mem_queue))
+    }
+
+    /// Adds a queue restored from a checkpoint.
+    pub(crate) fn insert_queue(&mut self, queue: &str, mem_queue: MemQueue) {
+        if let Some(first_retained_file_number) = mem_queue.first_retained_position() {
+            self.lowest_retained_file_number = Some(
+                self.lowest_retained_file_number
+                    .unwrap_or(first_retained_file_number)
+                    .min(first_retained_file_number),
+            );
+        }
+        self.insert_mem_queue(queue, mem_queue);
+    }
+
+    /// Returns the lowest file number retained by a queue, if any.
+    pub(crate) fn lowest_retained_file_number(&self) -> Option<FileNumber> {
+        self.queues
+            .values()
+            .filter_map(|mem_queue| mem_queue.first_retained_position())
+            .min()
+    }
+
+    /// Drops the records of `queue`, and makes it restart at `next_position`.
+    ///
+    /// This is used to recover a queue whose records were partially lost.
+    /// Returns the number of records dropped.
+    pub(crate) fn reset_queue(&mut self, queue: &str, next_position: u64) -> usize {
+        let num_records = self
+            .queues
+            .get(queue)
+            .map(|mem_queue| mem_queue.stats().num_records)
+            .unwrap_or(0);
+        self.replace_queue(queue, MemQueue::with_next_position(next_position));
+        num_records
+    }
+
+    /// Replaces the records of `queue` by the records of `mem_queue`.
+    ///
+    /// This is used by compaction, to move the records of a queue to a new file.
+    /// If one or more files should be removed,
+    /// returns the range of the files that should be removed
+    pub fn replace_queue(&mut self, queue: &str, mem_queue: MemQueue) -> Truncation {
+        if let Some(first_retained_file_number) = mem_queue.first_retained_position() {
+            if self.lowest_retained_file_number.is_none() {
+                self.lowest_retained_file_number = Some(first_retained_file_number);
+            }
+        }
+        self.insert_mem_queue(queue, mem_queue);
+        self.spill_if_needed();
+        if let Some(previous_lowest_retained_file_number) = self.lowest_retained_file_number {
+            self.update_lowest_retained_file_number(previous_lowest_retained_file_number)
+        } else {
+            Truncation::NoTruncation
+        }
+    }
+
+    // Computes the lowest file number retained by a queue, after records were removed.
+    fn update_lowest_retained_file_number(
+        &mut self,
+        previous_lowest_retained_file_number: FileNumber,
+    ) -> Truncation {
         let mut min_retained_file_number_opt: Option<FileNumber> = None;
         for queue in self.queues.values() {
             let queue_retained_file_opt = queue.first_retained_position();
@@ -186,22 +615,22 @@ mod tests {
         mem_queues.create_queue("droopy").unwrap();
         mem_queues.create_queue("fable").unwrap();
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(0), b"hello")
+            .append_record("droopy", 1.into(), 0, Some(0), None, b"hello")
             .is_ok());
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(1), b"happy")
+            .append_record("droopy", 1.into(), 0, Some(1), None, b"happy")
             .is_ok());
         assert!(mem_queues
-            .append_record("fable", 1.into(), Some(0), b"maitre")
+            .append_record("fable", 1.into(), 0, Some(0), None, b"maitre")
             .is_ok());
         assert!(mem_queues
-            .append_record("fable", 1.into(), Some(1), b"corbeau")
+            .append_record("fable", 1.into(), 0, Some(1), None, b"corbeau")
             .is_ok());
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(2), b"tax")
+            .append_record("droopy", 1.into(), 0, Some(2), None, b"tax")
             .is_ok());
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(3), b"payer")
+            .append_record("droopy", 1.into(), 0, Some(3), None, b"payer")
             .is_ok());
         assert_eq!(
             mem_queues.range("droopy", 0..).unwrap().next(),
@@ -221,45 +650,127 @@ mod tests {
         let mut mem_queues = MemQueues::default();
         mem_queues.create_queue("droopy").unwrap();
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(0), b"hello")
+            .append_record("droopy", 1.into(), 0, Some(0), None, b"hello")
             .is_ok());
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(1), b"happy")
+            .append_record("droopy", 1.into(), 0, Some(1), None, b"happy")
             .is_ok());
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(2), b"tax")
+            .append_record("droopy", 1.into(), 0, Some(2), None, b"tax")
             .is_ok());
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(3), b"payer")
+            .append_record("droopy", 1.into(), 0, Some(3), None, b"payer")
             .is_ok());
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(4), b"!")
+            .append_record("droopy", 1.into(), 0, Some(4), None, b"!")
             .is_ok());
         mem_queues
-            .append_record("droopy", 1.into(), Some(5), b"payer")
+            .append_record("droopy", 1.into(), 0, Some(5), None, b"payer")
             .unwrap();
-        assert_eq!(mem_queues.truncate("droopy", 3), Truncation::NoTruncation); // TODO fixme
+        // The records kept are in file 1, like the ones truncated: no file can be removed.
+        assert_eq!(mem_queues.truncate("droopy", 3), Truncation::NoTruncation);
         let droopy: Vec<(u64, &[u8])> = mem_queues.range("droopy", 0..).unwrap().collect();
         assert_eq!(&droopy[..], &[(4, &b"!"[..]), (5, &b"payer"[..]),]);
     }
 
+    #[test]
+    fn test_mem_queues_truncate_multiple_files() {
+        let mut mem_queues = MemQueues::default();
+        mem_queues.create_queue("droopy").unwrap();
+        mem_queues.create_queue("fable").unwrap();
+        mem_queues
+            .append_record("droopy", 1.into(), 0, Some(0), None, b"hello")
+            .unwrap();
+        mem_queues
+            .append_record("droopy", 1.into(), 10, Some(1), None, b"happy")
+            .unwrap();
+        mem_queues
+            .append_record("fable", 2.into(), 0, Some(0), None, b"tax")
+            .unwrap();
+        mem_queues
+            .append_record("droopy", 2.into(), 10, Some(2), None, b"payer")
+            .unwrap();
+        mem_queues
+            .append_record("droopy", 3.into(), 0, Some(3), None, b"!")
+            .unwrap();
+        // Record 1 of droopy is still in file 1.
+        assert_eq!(mem_queues.truncate("droopy", 0), Truncation::NoTruncation);
+        // File 1 is no longer retained by any queue.
+        assert_eq!(
+            mem_queues.truncate("droopy", 1),
+            Truncation::RemoveFiles(..FileNumber::from(2))
+        );
+        // File 2 is still retained by fable.
+        assert_eq!(mem_queues.truncate("droopy", 2), Truncation::NoTruncation);
+        assert_eq!(
+            mem_queues.truncate("fable", 0),
+            Truncation::RemoveFiles(..FileNumber::from(3))
+        );
+        assert_eq!(mem_queues.truncate("droopy", 3), Truncation::RemoveAllFiles);
+        // Once all files are removed, the next file appended to is retained again.
+        mem_queues
+            .append_record("fable", 4.into(), 0, Some(1), None, b"thanks")
+            .unwrap();
+        mem_queues
+            .append_record("fable", 5.into(), 0, Some(2), None, b"again")
+            .unwrap();
+        assert_eq!(
+            mem_queues.truncate("fable", 1),
+            Truncation::RemoveFiles(..FileNumber::from(5))
+        );
+    }
+
+    #[test]
+    fn test_mem_queues_truncate_missing_queue() {
+        // When replaying the log, the truncation of a queue may precede
+        // the record restoring its position.
+        let mut mem_queues = MemQueues::default();
+        mem_queues
+            .append_record("droopy", 1.into(), 0, Some(0), None, b"hello")
+            .unwrap();
+        assert_eq!(mem_queues.truncate("fable", 2), Truncation::NoTruncation);
+        assert!(!mem_queues.contains_queue("fable"));
+        mem_queues.touch("fable", 3).unwrap();
+        assert_eq!(mem_queues.next_position("fable").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_mem_queues_truncate_last_record() {
+        let mut mem_queues = MemQueues::default();
+        mem_queues.create_queue("droopy").unwrap();
+        mem_queues
+            .append_record("droopy", 1.into(), 0, Some(0), None, b"hello")
+            .unwrap();
+        mem_queues
+            .append_record("droopy", 1.into(), 0, Some(1), None, b"happy")
+            .unwrap();
+        mem_queues.truncate("droopy", 1);
+        assert_eq!(mem_queues.range("droopy", ..).unwrap().count(), 0);
+        assert_eq!(
+            mem_queues
+                .append_record("droopy", 1.into(), 0, None, None, b"tax")
+                .unwrap(),
+            Some(2)
+        );
+    }
+
     #[test]
     fn test_mem_queues_skip_yield_error() {
         let mut mem_queues = MemQueues::default();
         mem_queues.create_queue("droopy").unwrap();
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(0), b"hello")
+            .append_record("droopy", 1.into(), 0, Some(0), None, b"hello")
             .is_ok());
         assert!(matches!(
-            mem_queues.append_record("droopy", 1.into(), Some(2), b"happy"),
+            mem_queues.append_record("droopy", 1.into(), 0, Some(2), None, b"happy"),
             Err(AppendError::Future)
         ));
         assert!(matches!(
-            mem_queues.append_record("droopy", 1.into(), Some(3), b"happy"),
+            mem_queues.append_record("droopy", 1.into(), 0, Some(3), None, b"happy"),
             Err(AppendError::Future)
         ));
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(1), b"happy")
+            .append_record("droopy", 1.into(), 0, Some(1), None, b"happy")
             .is_ok());
         let droopy: Vec<(u64, &[u8])> = mem_queues.range("droopy", 0..).unwrap().collect();
         assert_eq!(&droopy[..], &[(0, &b"hello"[..]), (1, &b"happy"[..])]);
@@ -270,13 +781,13 @@ mod tests {
         let mut mem_queues = MemQueues::default();
         mem_queues.create_queue("droopy").unwrap();
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(0), b"hello")
+            .append_record("droopy", 1.into(), 0, Some(0), None, b"hello")
             .is_ok());
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(1), b"happy")
+            .append_record("droopy", 1.into(), 0, Some(1), None, b"happy")
             .is_ok());
         assert!(matches!(
-            mem_queues.append_record("droopy", 1.into(), Some(0), b"happy"),
+            mem_queues.append_record("droopy", 1.into(), 0, Some(0), None, b"happy"),
             Err(AppendError::Past)
         ));
     }
@@ -286,10 +797,10 @@ mod tests {
         let mut mem_queues = MemQueues::default();
         mem_queues.create_queue("droopy").unwrap();
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(0), b"hello")
+            .append_record("droopy", 1.into(), 0, Some(0), None, b"hello")
             .is_ok());
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(0), b"different")
+            .append_record("droopy", 1.into(), 0, Some(0), None, b"different")
             .is_ok()); //< the string is different
                        // Right now there are no checks, on the string being equal.
         let droopy: Vec<(u64, &[u8])> = mem_queues.range("droopy", 0..).unwrap().collect();
@@ -301,7 +812,7 @@ mod tests {
         let mut mem_queues = MemQueues::default();
         mem_queues.create_queue("droopy").unwrap();
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(5), b"hello")
+            .append_record("droopy", 1.into(), 0, Some(5), None, b"hello")
             .is_ok());
         let droopy: Vec<(u64, &[u8])> = mem_queues.range("droopy", 0..).unwrap().collect();
         assert_eq!(droopy, &[(5, &b"hello"[..])]);
@@ -312,13 +823,13 @@ mod tests {
         let mut mem_queues = MemQueues::default();
         mem_queues.create_queue("droopy").unwrap();
         assert!(mem_queues
-            .append_record("droopy", 1.into(), Some(5), b"hello")
+            .append_record("droopy", 1.into(), 0, Some(5), None, b"hello")
             .is_ok());
         assert!(mem_queues
-            .append_record("droopy", 1.into(), None, b"happy")
+            .append_record("droopy", 1.into(), 0, None, None, b"happy")
             .is_ok());
         assert!(mem_queues
-            .append_record("droopy", 1.into(), None, b"tax")
+            .append_record("droopy", 1.into(), 0, None, None, b"tax")
             .is_ok());
         let droopy: Vec<(u64, &[u8])> = mem_queues.range("droopy", 5..).unwrap().collect();
         assert_eq!(
@@ -326,4 +837,114 @@ mod tests {
             &[(5, &b"hello"[..]), (6, &b"happy"[..]), (7, &b"tax"[..])]
         );
     }
+
+    #[test]
+    fn test_mem_queues_delete_queue() {
+        let mut mem_queues = MemQueues::default();
+        mem_queues.create_queue("droopy").unwrap();
+        mem_queues.create_queue("fable").unwrap();
+        mem_queues
+            .append_record("droopy", 1.into(), 0, Some(0), None, b"hello")
+            .unwrap();
+        mem_queues
+            .append_record("fable", 2.into(), 0, Some(0), None, b"maitre")
+            .unwrap();
+        assert_eq!(
+            mem_queues.delete_queue("droopy").unwrap(),
+            Truncation::RemoveFiles(..2.into())
+        );
+        assert!(!mem_queues.contains_queue("droopy"));
+        assert!(mem_queues.delete_queue("droopy").is_err());
+        assert_eq!(
+            mem_queues.delete_queue("fable").unwrap(),
+            Truncation::RemoveAllFiles
+        );
+        mem_queues.create_queue("droopy").unwrap();
+        assert_eq!(mem_queues.range("droopy", ..).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_mem_queues_stats() {
+        let mut mem_queues = MemQueues::default();
+        mem_queues.create_queue("droopy").unwrap();
+        assert_eq!(
+            mem_queues.queue_stats("droopy").unwrap(),
+            QueueStats::default()
+        );
+        mem_queues
+            .append_record("droopy", 1.into(), 0, Some(0), None, b"hello")
+            .unwrap();
+        mem_queues
+            .append_record("droopy", 1.into(), 0, Some(1), None, b"happy")
+            .unwrap();
+        mem_queues
+            .append_record("droopy", 1.into(), 0, Some(2), None, b"tax")
+            .unwrap();
+        mem_queues.truncate("droopy", 0);
+        assert_eq!(
+            mem_queues.queue_stats("droopy").unwrap(),
+            QueueStats {
+                num_records: 2,
+                num_bytes: 8,
+                first_position: Some(1),
+                last_position: Some(2),
+            }
+        );
+        assert!(mem_queues.memory_usage() >= 8);
+        assert!(mem_queues.queue_stats("fable").is_err());
+    }
+
+    fn spilled_positions(mem_queues: &MemQueues, queue: &str) -> Vec<u64> {
+        mem_queues
+            .range_payloads(queue, ..)
+            .unwrap()
+            .filter(|(_, payload)| matches!(payload, Payload::Spilled { .. }))
+            .map(|(position, _)| position)
+            .collect()
+    }
+
+    #[test]
+    fn test_mem_queues_spill_oldest_records_first() {
+        let mut mem_queues = MemQueues::default();
+        mem_queues.set_spill(Arc::new(crate::storage::MemoryStorage::default()), Some(10));
+        mem_queues.create_queue("droopy").unwrap();
+        mem_queues.create_queue("fable").unwrap();
+        mem_queues.create_queue("empty").unwrap();
+        mem_queues
+            .append_record("droopy", 1.into(), 0, Some(0), None, b"hello")
+            .unwrap();
+        mem_queues
+            .append_record("fable", 1.into(), 100, Some(0), None, b"happy")
+            .unwrap();
+        assert_eq!(mem_queues.in_memory_num_bytes, 10);
+        assert!(spilled_positions(&mem_queues, "droopy").is_empty());
+        mem_queues
+            .append_record("droopy", 1.into(), 200, Some(1), None, b"tax")
+            .unwrap();
+        // The oldest record, across all queues, is spilled.
+        assert_eq!(spilled_positions(&mem_queues, "droopy"), vec![0]);
+        assert!(spilled_positions(&mem_queues, "fable").is_empty());
+        assert_eq!(mem_queues.in_memory_num_bytes, 8);
+        mem_queues
+            .append_record("fable", 2.into(), 0, Some(1), None, b"thanks")
+            .unwrap();
+        assert_eq!(spilled_positions(&mem_queues, "droopy"), vec![0]);
+        assert_eq!(spilled_positions(&mem_queues, "fable"), vec![0]);
+        assert_eq!(mem_queues.in_memory_num_bytes, 9);
+        assert_eq!(mem_queues.num_bytes(), 19);
+        mem_queues.truncate("droopy", 0);
+        assert_eq!(mem_queues.in_memory_num_bytes, 9);
+        assert_eq!(mem_queues.num_bytes(), 14);
+        mem_queues.delete_queue("fable").unwrap();
+        assert_eq!(mem_queues.in_memory_num_bytes, 3);
+        assert_eq!(mem_queues.num_bytes(), 3);
+        assert_eq!(
+            mem_queues.spill_order.iter().collect::<Vec<_>>(),
+            vec![&((FileNumber::from(1), 200), "droopy".to_string())]
+        );
+        mem_queues.truncate("droopy", 1);
+        assert_eq!(mem_queues.in_memory_num_bytes, 0);
+        assert_eq!(mem_queues.num_bytes(), 0);
+        assert!(mem_queues.spill_order.is_empty());
+    }
 }
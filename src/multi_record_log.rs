@@ -1,102 +1,304 @@
-use std::ops::{RangeBounds, RangeTo};
+use std::ops::{Range, RangeBounds};
 use std::path::Path;
+use std::sync::Arc;
 
-use crate::error::{AppendError, CreateQueueError, MissingQueue, TruncateError};
-use crate::mem::Truncation;
+use bytes::Bytes;
+use futures_util::stream::Stream;
+
+use crate::checkpoint::Checkpoint;
+use crate::compression::{Codec, Compression};
+use crate::error::{
+    AppendError, CheckpointError, CompactError, CreateQueueError, DeleteQueueError, MissingQueue,
+    TruncateError,
+};
+use crate::mem::{self, MemQueue, MemQueues, QueueStats, RangeRecord};
 use crate::position::FileNumber;
 use crate::record::ReadRecordError;
 use crate::rolling::{Record, RecordLogReader};
-use crate::{mem, rolling};
+use crate::storage::{LocalStorage, Storage};
+use crate::writer::{CapacityLimits, MultiRecordLogWriter};
+use crate::{Corruption, MultiRecordLogOptions, RecoveryMode, RecoveryReport};
 
 pub struct MultiRecordLog {
-    record_log_writer: rolling::RecordLogWriter,
+    writer: MultiRecordLogWriter,
     in_mem_queues: mem::MemQueues,
+    recovery_report: RecoveryReport,
 }
 
 impl MultiRecordLog {
+    /// Opens the log with the default options.
     pub async fn open(directory_path: &Path) -> Result<Self, ReadRecordError> {
-        let mut record_log_reader = RecordLogReader::open(directory_path).await?;
-        let mut in_mem_queues = crate::mem::MemQueues::default();
-        loop {
-            if let Some((file_number, record)) = record_log_reader.read_record().await? {
-                match record {
-                    Record::AppendRecord {
-                        position,
-                        queue,
-                        payload,
-                    } => {
-                        in_mem_queues
-                            .append_record(queue, file_number, Some(position), payload)
-                            .map_err(|_| ReadRecordError::Corruption)?;
-                    }
-                    Record::Truncate { position, queue } => {
-                        in_mem_queues.truncate(queue, position);
-                    }
-                    Record::Touch { queue, position } => {
-                        in_mem_queues
-                            .touch(queue, position)
-                            .map_err(|_| ReadRecordError::Corruption)?;
-                    }
-                }
-            } else {
-                break;
-            }
-        }
-        let record_log_writer = record_log_reader.into_writer().await?;
+        Self::open_with_options(directory_path, MultiRecordLogOptions::default()).await
+    }
+
+    pub async fn open_with_options(
+        directory_path: &Path,
+        options: MultiRecordLogOptions,
+    ) -> Result<Self, ReadRecordError> {
+        let storage = LocalStorage::open(directory_path).await?;
+        Self::open_with_storage(Arc::new(storage), options).await
+    }
+
+    /// Opens the log stored in `storage`, rather than in a local directory.
+    pub async fn open_with_storage(
+        storage: Arc<dyn Storage>,
+        options: MultiRecordLogOptions,
+    ) -> Result<Self, ReadRecordError> {
+        let (record_log_reader, in_mem_queues, recovery_report) = if let Some(replayed) =
+            Self::replay_from_checkpoint(&storage, &options).await?
+        {
+            replayed
+        } else {
+            let mut record_log_reader = RecordLogReader::open_with_storage(storage.clone()).await?;
+            let mut in_mem_queues = MemQueues::default();
+            in_mem_queues.set_spill(storage, options.spill_num_bytes);
+            // Unless the first files of the log were removed, every queue is created
+            // by a record replayed.
+            let all_queues_known = record_log_reader
+                .directory()
+                .file_numbers()
+                .next()
+                .map(|first_file_number| first_file_number == FileNumber::from(1))
+                .unwrap_or(true);
+            let recovery_report = replay(
+                &mut record_log_reader,
+                &mut in_mem_queues,
+                options.recovery_mode,
+                all_queues_known,
+            )
+            .await?;
+            (record_log_reader, in_mem_queues, recovery_report)
+        };
+        let record_log_writer = record_log_reader
+            .into_writer(
+                options.sync_policy,
+                options.rollover_policy,
+                options.resume_last_file,
+            )
+            .await?;
+        let mut writer = MultiRecordLogWriter::new(record_log_writer);
+        writer.set_capacity_limits(options.capacity_limits);
+        writer.set_checkpoint_interval(options.checkpoint_interval);
+        writer.set_wall_clock_timestamps(options.wall_clock_timestamps);
+        writer.set_compression(options.compression);
         Ok(MultiRecordLog {
-            record_log_writer,
+            writer,
             in_mem_queues,
+            recovery_report,
         })
     }
 
+    /// Restores the in-memory queues from the checkpoint, and replays the records
+    /// written after it.
+    ///
+    /// Returns None if there is no checkpoint, or if it does not agree with the log,
+    /// in which case the whole log needs to be replayed.
+    async fn replay_from_checkpoint(
+        storage: &Arc<dyn Storage>,
+        options: &MultiRecordLogOptions,
+    ) -> Result<Option<(RecordLogReader, MemQueues, RecoveryReport)>, ReadRecordError> {
+        let mut record_log_reader = RecordLogReader::open_with_storage(storage.clone()).await?;
+        let checkpoint_opt = record_log_reader
+            .read_checkpoint()
+            .await?
+            .and_then(|checkpoint| Checkpoint::deserialize(&checkpoint));
+        let checkpoint = match checkpoint_opt {
+            Some(checkpoint) if checkpoint.agrees_with(record_log_reader.directory()) => checkpoint,
+            _ => return Ok(None),
+        };
+        record_log_reader
+            .seek(checkpoint.file_number, checkpoint.offset)
+            .await?;
+        let mut in_mem_queues = checkpoint.mem_queues;
+        in_mem_queues.set_spill(storage.clone(), options.spill_num_bytes);
+        // The queues created before the checkpoint are restored from it.
+        let recovery_report = match replay(
+            &mut record_log_reader,
+            &mut in_mem_queues,
+            options.recovery_mode,
+            true,
+        )
+        .await
+        {
+            Ok(recovery_report) => recovery_report,
+            Err(ReadRecordError::Corruption) => return Ok(None),
+            Err(read_record_error) => return Err(read_record_error),
+        };
+        // The records of the checkpoint held in removed files should have been
+        // truncated by the records replayed.
+        let first_file_number_opt = record_log_reader.directory().file_numbers().next();
+        if let Some(lowest_retained_file_number) = in_mem_queues.lowest_retained_file_number() {
+            if Some(lowest_retained_file_number) < first_file_number_opt {
+                return Ok(None);
+            }
+        }
+        // The records of the checkpoint are spilled. Unless spilling is enabled,
+        // they are held in memory, as if the log had been replayed.
+        if options.spill_num_bytes.is_none() {
+            match in_mem_queues.load_spilled_records().await {
+                Ok(()) => {}
+                Err(ReadRecordError::Corruption) => return Ok(None),
+                Err(read_record_error) => return Err(read_record_error),
+            }
+        }
+        Ok(Some((record_log_reader, in_mem_queues, recovery_report)))
+    }
+
+    /// Splits the log into its writer and its in-memory queues.
+    pub(crate) fn into_parts(self) -> (MultiRecordLogWriter, mem::MemQueues) {
+        (self.writer, self.in_mem_queues)
+    }
+
     #[cfg(test)]
     pub fn num_files(&self) -> usize {
-        self.record_log_writer.num_files()
+        self.writer.num_files()
+    }
+
+    /// Returns the corruptions skipped upon opening the log in `RecoveryMode::BestEffort`.
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
+    }
+
+    /// Sets the limits beyond which appends are rejected with `AppendError::Full`.
+    pub fn set_capacity_limits(&mut self, capacity_limits: CapacityLimits) {
+        self.writer.set_capacity_limits(capacity_limits);
+    }
+
+    /// Sets the compression of the payloads of the records appended to `queue` from now on,
+    /// overriding the compression of the log. `None` restores the compression of the log.
+    ///
+    /// The override is not persisted: it applies until the log is closed, whether the queue
+    /// exists yet or not.
+    pub fn set_queue_compression(&mut self, queue: &str, compression_opt: Option<Compression>) {
+        self.writer.set_queue_compression(queue, compression_opt);
+    }
+
+    /// Returns true if the log hit an IO error that left its files in an unknown state.
+    ///
+    /// A poisoned log refuses any new write, and needs to be reopened.
+    /// Upon reopening, the in-memory queues are rebuilt from the files.
+    pub fn is_poisoned(&self) -> bool {
+        self.writer.is_poisoned()
+    }
+
+    /// Flushes the records written so far, and syncs them according to the sync policy.
+    ///
+    /// Once flushed, the records written without flush are added to the in-memory queues.
+    #[cfg(test)]
+    pub(crate) async fn flush(&mut self) -> Result<(), AppendError> {
+        self.writer.flush(&mut self.in_mem_queues).await
+    }
+
+    /// Rolls the log over to a new file, regardless of the rollover policy.
+    #[cfg(any(test, feature = "simulation"))]
+    pub(crate) async fn rollover(&mut self) -> Result<(), AppendError> {
+        self.writer.rollover(&mut self.in_mem_queues).await
     }
 
     pub async fn create_queue(&mut self, queue: &str) -> Result<(), CreateQueueError> {
-        self.record_log_writer.roll_if_needed().await?;
-        self.in_mem_queues.create_queue(queue)?;
-        let record = Record::Touch { queue, position: 0 };
-        self.record_log_writer.write_record(record).await?;
-        self.record_log_writer.flush().await?;
-        Ok(())
+        self.writer
+            .create_queue(&mut self.in_mem_queues, queue)
+            .await
+    }
+
+    /// Deletes the queue and all of its records.
+    ///
+    /// The queue is only removed from memory once the deletion was successfully written
+    /// and flushed. If an IO error occurs in the process, the log is poisoned.
+    ///
+    /// Files that are not retained by any queue anymore are then removed.
+    pub async fn delete_queue(&mut self, queue: &str) -> Result<(), DeleteQueueError> {
+        self.writer
+            .delete_queue(&mut self.in_mem_queues, queue)
+            .await
     }
 
     /// Appends a record to the log.
     ///
     /// The local_position argument can optionally be passed to enforce nilpotence.
-    /// TODO if an io Error is encounterred, the in mem queue and the record log will
-    /// be in an inconsistent state.
+    ///
+    /// The record is added to the in-memory queue only once it was successfully written
+    /// and flushed. If an IO error occurs in the process, the log is poisoned.
+    ///
+    /// If appending the record would exceed one of the capacity limits, the record
+    /// is rejected with `AppendError::Full`.
     pub async fn append_record(
         &mut self,
         queue: &str,
         position: Option<u64>,
         payload: &[u8],
     ) -> Result<Option<u64>, AppendError> {
-        if !self.in_mem_queues.contains_queue(queue) {
-            return Err(AppendError::MissingQueue(queue.to_string()));
-        }
-        let file_number = self.record_log_writer.roll_if_needed().await?;
-        let append_record_res =
-            self.in_mem_queues
-                .append_record(queue, file_number, position, payload)?;
-        let local_position = if let Some(local_position) = append_record_res {
-            local_position
-        } else {
-            return Ok(None);
-        };
-        let record = Record::AppendRecord {
-            position: local_position,
-            queue,
-            payload,
-        };
-        self.record_log_writer.write_record(record).await?;
-        self.record_log_writer.flush().await?;
-        Ok(Some(local_position))
+        self.writer
+            .append_record(&mut self.in_mem_queues, queue, position, None, payload)
+            .await
+    }
+
+    /// Same as `append_record`, but timestamps the record with `timestamp`, rather than
+    /// with the wall clock.
+    ///
+    /// Timestamps can be in any unit, but are expected to be non-decreasing within a queue.
+    /// See `position_for_timestamp`.
+    pub async fn append_record_with_timestamp(
+        &mut self,
+        queue: &str,
+        position: Option<u64>,
+        timestamp: u64,
+        payload: &[u8],
+    ) -> Result<Option<u64>, AppendError> {
+        self.writer
+            .append_record(
+                &mut self.in_mem_queues,
+                queue,
+                position,
+                Some(timestamp),
+                payload,
+            )
+            .await
+    }
+
+    /// Appends a record to the log, without flushing it.
+    ///
+    /// The record is only written to the file (and possibly synced) on the next `flush`,
+    /// and only then added to the in-memory queue.
+    /// This makes it possible to commit several records at once.
+    #[cfg(test)]
+    pub(crate) async fn append_record_without_flush(
+        &mut self,
+        queue: &str,
+        position: Option<u64>,
+        payload: &[u8],
+    ) -> Result<Option<u64>, AppendError> {
+        self.writer
+            .append_record_without_flush(&self.in_mem_queues, queue, position, payload)
+            .await
+    }
+
+    /// Appends a batch of records to the log, and flushes them at once.
+    ///
+    /// `position` is the position of the first record of the batch, and can optionally be
+    /// passed to enforce nilpotence. If the batch was already appended, meaning that its
+    /// last record is the last record of the queue, no record is appended.
+    ///
+    /// Returns the range of positions assigned to the records, or `None` if no record
+    /// was appended, either because the batch was already appended or because it was empty.
+    pub async fn append_records<'a>(
+        &mut self,
+        queue: &str,
+        position: Option<u64>,
+        payloads: impl Iterator<Item = &'a [u8]>,
+    ) -> Result<Option<Range<u64>>, AppendError> {
+        self.writer
+            .append_records(&mut self.in_mem_queues, queue, position, payloads)
+            .await
     }
 
     /// Returns the first record with position greater of equal to position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range holds records spilled, which only happens if spilling is
+    /// enabled. Use `range_stream` to read such queues.
+    /// See `MultiRecordLogOptions::with_spill_num_bytes`.
     pub fn range<'a, R>(
         &'a self,
         queue: &str,
@@ -108,34 +310,335 @@ impl MultiRecordLog {
         self.in_mem_queues.range(queue, range)
     }
 
-    async fn log_positions(&mut self) -> Result<(), TruncateError> {
-        for (queue, position) in self.in_mem_queues.empty_queue_positions() {
-            let record = Record::Touch { queue, position };
-            self.record_log_writer.write_record(record).await?;
-        }
-        self.record_log_writer.flush().await?;
-        Ok(())
+    /// Same as `range`, but returns payloads that do not borrow the log, so that they
+    /// can be held across an `.await` or sent to another thread.
+    ///
+    /// The payloads are not copied: they share the memory of the in-memory queue, which
+    /// is only freed once they are dropped, even if the records are truncated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range holds records spilled. Use `range_bytes_stream` instead.
+    pub fn range_bytes<'a, R>(
+        &'a self,
+        queue: &str,
+        range: R,
+    ) -> Result<impl Iterator<Item = (u64, Bytes)> + 'a, MissingQueue>
+    where
+        R: RangeBounds<u64> + 'static,
+    {
+        self.in_mem_queues.range_bytes(queue, range)
+    }
+
+    /// Same as `range`, but the payloads of the records spilled are read back from
+    /// the log files, without blocking.
+    ///
+    /// The payloads of the records held in memory are borrowed.
+    ///
+    /// A record spilled that cannot be read back, because the log files were altered,
+    /// yields an error. The stream goes on with the following records.
+    pub fn range_stream<'a, R>(
+        &'a self,
+        queue: &str,
+        range: R,
+    ) -> Result<impl Stream<Item = RangeRecord<'a>> + 'a, MissingQueue>
+    where
+        R: RangeBounds<u64> + 'static,
+    {
+        self.in_mem_queues.range_stream(queue, range)
+    }
+
+    /// Same as `range_bytes`, but the payloads of the records spilled are read back from
+    /// the log files, as for `range_stream`.
+    pub fn range_bytes_stream<'a, R>(
+        &'a self,
+        queue: &str,
+        range: R,
+    ) -> Result<impl Stream<Item = Result<(u64, Bytes), ReadRecordError>> + 'a, MissingQueue>
+    where
+        R: RangeBounds<u64> + 'static,
+    {
+        self.in_mem_queues.range_bytes_stream(queue, range)
+    }
+
+    /// Returns the timestamp of the record of `queue` at `position`, if it has one.
+    pub fn timestamp(&self, queue: &str, position: u64) -> Result<Option<u64>, MissingQueue> {
+        self.in_mem_queues.timestamp(queue, position)
+    }
+
+    /// Returns the position of the first record of `queue` with a timestamp greater or equal
+    /// to `timestamp`, or the next position of the queue if there is none.
+    ///
+    /// Records without timestamp are considered older than any timestamp.
+    /// For instance, the records appended in the last ten minutes are the records of
+    /// `range(queue, position_for_timestamp(queue, ten_minutes_ago)..)`.
+    pub fn position_for_timestamp(&self, queue: &str, timestamp: u64) -> Result<u64, MissingQueue> {
+        self.in_mem_queues.position_for_timestamp(queue, timestamp)
+    }
+
+    /// Rewrites the records of the queues that retain old files, so that these files
+    /// can be removed. Positions are preserved.
+    ///
+    /// Files are otherwise only removed once all of the queues are truncated past them,
+    /// so a single idle queue can retain many files.
+    ///
+    /// The records of the queues are copied as a whole, so the cost of a compaction
+    /// is proportional to the number of bytes held by these queues.
+    pub async fn compact(&mut self) -> Result<(), CompactError> {
+        self.writer.compact(&mut self.in_mem_queues).await
+    }
+
+    /// Writes a checkpoint of the queues, so that reopening the log only requires
+    /// replaying the records written after it.
+    ///
+    /// The checkpoint holds the location of the records of the queues in the log files,
+    /// but not their payloads, so its cost is proportional to the number of records.
+    /// Upon opening, the payloads of the records restored from it are read back from the
+    /// log files, right away unless spilling is enabled. It is replaced by the next checkpoint.
+    ///
+    /// If the checkpoint does not agree with the log upon opening, because the log
+    /// was truncated past it or because it is corrupted, the whole log is replayed.
+    pub async fn checkpoint(&mut self) -> Result<(), CheckpointError> {
+        self.writer.checkpoint(&mut self.in_mem_queues).await
     }
 
+    /// Returns the number of bytes allocated in memory to hold the records of all queues.
+    pub fn memory_usage(&self) -> usize {
+        self.in_mem_queues.memory_usage()
+    }
+
+    /// Returns the number of bytes of the log files and of the checkpoint on disk.
+    pub fn disk_usage(&self) -> u64 {
+        self.writer.disk_usage()
+    }
+
+    /// Returns the number of records, their size, and the positions of the first and last
+    /// records held by `queue`.
+    pub fn queue_stats(&self, queue: &str) -> Result<QueueStats, MissingQueue> {
+        self.in_mem_queues.queue_stats(queue)
+    }
+
+    /// Truncates the queue up to `position`, included.
+    ///
+    /// The in-memory queue is only truncated once the truncation was successfully
+    /// written and flushed. If an IO error occurs in the process, the log is poisoned.
+    ///
+    /// Files that only contain truncated records are then removed. Failing to remove them
+    /// does not poison the log.
     pub async fn truncate(&mut self, queue: &str, position: u64) -> Result<(), TruncateError> {
-        if !self.in_mem_queues.contains_queue(queue) {
-            return Err(TruncateError::MissingQueue(queue.to_string()));
+        self.writer
+            .truncate(&mut self.in_mem_queues, queue, position)
+            .await
+    }
+}
+
+/// Record that does not agree with the records replayed before it.
+struct Inconsistency {
+    queue: String,
+    num_records_lost: u64,
+}
+
+impl Inconsistency {
+    fn new(queue: &str, num_records_lost: u64) -> Self {
+        Inconsistency {
+            queue: queue.to_string(),
+            num_records_lost,
         }
-        let truncation = self.in_mem_queues.truncate(queue, position);
-        let file_number = self.record_log_writer.roll_if_needed().await?;
-        self.record_log_writer
-            .write_record(Record::Truncate { position, queue })
-            .await?;
-        self.log_positions().await?;
-        let files_to_remove: RangeTo<FileNumber> = match truncation {
-            Truncation::NoTruncation => {
-                return Ok(());
+    }
+}
+
+/// Applies the records read by `record_log_reader` to `in_mem_queues`.
+///
+/// In `RecoveryMode::BestEffort`, corruptions are skipped and returned in the report.
+///
+/// If `all_queues_known` is true, the creation of every queue is either replayed or
+/// already in `in_mem_queues`, so that deleting an unknown queue is an inconsistency.
+async fn replay(
+    record_log_reader: &mut RecordLogReader,
+    in_mem_queues: &mut MemQueues,
+    recovery_mode: RecoveryMode,
+    all_queues_known: bool,
+) -> Result<RecoveryReport, ReadRecordError> {
+    let mut recovery_report = RecoveryReport::default();
+    // Queue being rewritten by a compaction, with the file it is rewritten to,
+    // and its rewritten records.
+    let mut compaction_opt: Option<(String, FileNumber, MemQueue)> = None;
+    loop {
+        let (queue_opt, num_records_lost) = match record_log_reader.read_record_with_offset().await
+        {
+            Ok(Some((file_number, offset, record))) => {
+                match apply_record(
+                    in_mem_queues,
+                    &mut compaction_opt,
+                    all_queues_known,
+                    file_number,
+                    offset,
+                    record,
+                ) {
+                    Ok(()) => continue,
+                    Err(inconsistency) => {
+                        (Some(inconsistency.queue), inconsistency.num_records_lost)
+                    }
+                }
             }
-            Truncation::RemoveFiles(files_to_remove) => ..files_to_remove.end.min(file_number),
-            Truncation::RemoveAllFiles => ..file_number,
+            Ok(None) => break,
+            Err(ReadRecordError::Corruption) => (None, 0),
+            Err(io_error) => return Err(io_error),
         };
+        if recovery_mode == RecoveryMode::Strict {
+            return Err(ReadRecordError::Corruption);
+        }
+        let (file_number, offset) = record_log_reader
+            .position()
+            .expect("a file should be being read");
+        recovery_report.corruptions.push(Corruption {
+            file_number,
+            offset,
+            queue: queue_opt,
+            num_records_lost,
+        });
+    }
+    Ok(recovery_report)
+}
 
-        self.record_log_writer.truncate(files_to_remove).await?;
-        Ok(())
+/// Applies `record` to `in_mem_queues`.
+///
+/// A record inconsistent with the queues is either skipped, or the queue is reset
+/// to accept it if records were lost before it.
+fn apply_record(
+    in_mem_queues: &mut MemQueues,
+    compaction_opt: &mut Option<(String, FileNumber, MemQueue)>,
+    all_queues_known: bool,
+    file_number: FileNumber,
+    offset: u64,
+    record: Record,
+) -> Result<(), Inconsistency> {
+    let decompressed_payload;
+    let record = match record {
+        Record::AppendRecord {
+            position,
+            queue,
+            timestamp,
+            codec,
+            payload,
+        } if codec != Codec::None => {
+            // A payload that cannot be decompressed is lost.
+            decompressed_payload = codec
+                .decompress(payload)
+                .map_err(|_| Inconsistency::new(queue, 1))?;
+            Record::AppendRecord {
+                position,
+                queue,
+                timestamp,
+                codec: Codec::None,
+                payload: &decompressed_payload,
+            }
+        }
+        record => record,
+    };
+    // The records of a compaction are written contiguously. Any other record
+    // means that the compaction was interrupted.
+    if let Some((compacted_queue, compaction_file_number, mem_queue)) = compaction_opt.as_mut() {
+        match record {
+            Record::AppendRecord {
+                position,
+                queue,
+                timestamp,
+                payload,
+                ..
+            } if queue == compacted_queue && file_number == *compaction_file_number => {
+                if mem_queue
+                    .append_record(file_number, offset, Some(position), timestamp, payload)
+                    .is_err()
+                {
+                    // The compaction is ignored, and the records it rewrites are kept.
+                    *compaction_opt = None;
+                    return Err(Inconsistency::new(queue, 0));
+                }
+                return Ok(());
+            }
+            Record::CompactionEnd { queue }
+                if queue == compacted_queue && file_number == *compaction_file_number =>
+            {
+                let (compacted_queue, _, mem_queue) = compaction_opt.take().unwrap();
+                in_mem_queues.replace_queue(&compacted_queue, mem_queue);
+                return Ok(());
+            }
+            _ => {
+                *compaction_opt = None;
+            }
+        }
+    }
+    match record {
+        Record::AppendRecord {
+            position,
+            queue,
+            timestamp,
+            payload,
+            ..
+        } => match in_mem_queues.append_record(
+            queue,
+            file_number,
+            offset,
+            Some(position),
+            timestamp,
+            payload,
+        ) {
+            Ok(_) => {}
+            Err(AppendError::Future) => {
+                // The records preceding this one were lost.
+                let next_position = in_mem_queues.next_position(queue).unwrap_or_default();
+                let num_records_dropped = in_mem_queues.reset_queue(queue, position);
+                in_mem_queues
+                    .append_record(
+                        queue,
+                        file_number,
+                        offset,
+                        Some(position),
+                        timestamp,
+                        payload,
+                    )
+                    .expect("a reset queue should accept its next record");
+                let num_records_lost = num_records_dropped as u64 + position - next_position;
+                return Err(Inconsistency::new(queue, num_records_lost));
+            }
+            Err(_) => {
+                return Err(Inconsistency::new(queue, 1));
+            }
+        },
+        Record::Truncate { position, queue } => {
+            in_mem_queues.truncate(queue, position);
+        }
+        Record::Touch { queue, position } => {
+            if in_mem_queues.touch(queue, position).is_err() {
+                let next_position = in_mem_queues.next_position(queue).unwrap_or_default();
+                if position < next_position {
+                    return Err(Inconsistency::new(queue, 0));
+                }
+                // The queue is empty as of this record, and the records preceding it were lost.
+                let num_records_dropped = in_mem_queues.reset_queue(queue, position);
+                let num_records_lost = num_records_dropped as u64 + position - next_position;
+                return Err(Inconsistency::new(queue, num_records_lost));
+            }
+        }
+        Record::DeleteQueue { queue } => {
+            // Unless the files holding the creation of the queue were removed,
+            // the queue deleted should exist.
+            if in_mem_queues.delete_queue(queue).is_err() && all_queues_known {
+                return Err(Inconsistency::new(queue, 0));
+            }
+        }
+        Record::CompactionStart { position, queue } => {
+            *compaction_opt = Some((
+                queue.to_string(),
+                file_number,
+                MemQueue::with_next_position(position),
+            ));
+        }
+        Record::CompactionEnd { queue } => {
+            // The start of the compaction was not found.
+            return Err(Inconsistency::new(queue, 0));
+        }
     }
+    Ok(())
 }
@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use crate::rolling::{RolloverPolicy, SyncPolicy};
+use crate::{CapacityLimits, Compression, RecoveryMode};
+
+/// Options used to open a `MultiRecordLog`.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use mrecordlog::{MultiRecordLogOptions, SyncPolicy};
+///
+/// let options = MultiRecordLogOptions::default()
+///     .with_sync_policy(SyncPolicy::OnDelay(Duration::from_millis(100)))
+///     .with_rollover_num_bytes(10_000_000)
+///     .with_rollover_delay(Duration::from_secs(600))
+///     .with_checkpoint_interval(Duration::from_secs(60));
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MultiRecordLogOptions {
+    pub(crate) sync_policy: SyncPolicy,
+    pub(crate) rollover_policy: RolloverPolicy,
+    pub(crate) capacity_limits: CapacityLimits,
+    pub(crate) checkpoint_interval: Option<Duration>,
+    pub(crate) recovery_mode: RecoveryMode,
+    pub(crate) resume_last_file: bool,
+    pub(crate) spill_num_bytes: Option<usize>,
+    pub(crate) wall_clock_timestamps: bool,
+    pub(crate) compression: Compression,
+}
+
+impl MultiRecordLogOptions {
+    /// Sets when the data written is synced. Defaults to `SyncPolicy::OnAppend`.
+    pub fn with_sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.sync_policy = sync_policy;
+        self
+    }
+
+    /// Sets the number of bytes after which the log rolls over to a new file.
+    /// Defaults to 50MB.
+    pub fn with_rollover_num_bytes(mut self, num_bytes: u64) -> Self {
+        self.rollover_policy.num_bytes = num_bytes;
+        self
+    }
+
+    /// If true, records are appended to the last file of the log upon opening, rather than
+    /// to a new file, until the log rolls over. Defaults to false.
+    ///
+    /// This keeps the number of files low if the log is reopened often.
+    pub fn with_resume_last_file(mut self, resume_last_file: bool) -> Self {
+        self.resume_last_file = resume_last_file;
+        self
+    }
+
+    /// Makes the log roll over to a new file once the active file is older than `delay`.
+    ///
+    /// See `RolloverPolicy::delay`.
+    pub fn with_rollover_delay(mut self, delay: Duration) -> Self {
+        self.rollover_policy.delay = Some(delay);
+        self
+    }
+
+    /// Sets the limits beyond which appends are rejected. Defaults to no limit.
+    pub fn with_capacity_limits(mut self, capacity_limits: CapacityLimits) -> Self {
+        self.capacity_limits = capacity_limits;
+        self
+    }
+
+    /// Makes the log write a checkpoint once the last one is older than `interval`.
+    /// Defaults to no periodic checkpoint.
+    ///
+    /// As for `SyncPolicy::OnDelay`, there is no background task: the checkpoint is
+    /// only written when records are appended. See `MultiRecordLog::checkpoint`.
+    ///
+    /// A periodic checkpoint that fails does not fail the append that triggered it: the
+    /// error is logged with `tracing`. As for any sync, failing to sync the log before
+    /// writing the checkpoint poisons the log.
+    pub fn with_checkpoint_interval(mut self, interval: Duration) -> Self {
+        self.checkpoint_interval = Some(interval);
+        self
+    }
+
+    /// Makes the log spill the oldest records once the payloads held in memory exceed
+    /// `num_bytes`, across all queues. Defaults to holding all of the records in memory.
+    ///
+    /// The payloads of the records spilled are dropped from memory, and read back from
+    /// the log files by `MultiRecordLog::range_stream`, which makes reading them slower.
+    /// `MultiRecordLog::range` panics on them. They are still accounted for by the
+    /// capacity limits.
+    pub fn with_spill_num_bytes(mut self, num_bytes: usize) -> Self {
+        self.spill_num_bytes = Some(num_bytes);
+        self
+    }
+
+    /// If true, records appended without timestamp are timestamped with the wall clock,
+    /// in milliseconds since the UNIX epoch. Defaults to false.
+    ///
+    /// See `MultiRecordLog::position_for_timestamp`.
+    pub fn with_wall_clock_timestamps(mut self, wall_clock_timestamps: bool) -> Self {
+        self.wall_clock_timestamps = wall_clock_timestamps;
+        self
+    }
+
+    /// Sets the compression of the payloads of the records appended from now on.
+    /// Defaults to `Compression::None`.
+    ///
+    /// The records already written are read back whatever their compression.
+    /// See `MultiRecordLog::set_queue_compression` to override it for a queue.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets how a corrupted log is opened. Defaults to `RecoveryMode::Strict`.
+    pub fn with_recovery_mode(mut self, recovery_mode: RecoveryMode) -> Self {
+        self.recovery_mode = recovery_mode;
+        self
+    }
+}
@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Default)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
 pub struct FileNumber(u32);
 
 impl From<u32> for FileNumber {
@@ -9,6 +9,12 @@ impl From<u32> for FileNumber {
     }
 }
 
+impl From<FileNumber> for u32 {
+    fn from(file_number: FileNumber) -> Self {
+        file_number.0
+    }
+}
+
 impl FileNumber {
     /// Increment the position and returns the previous value.
     pub fn inc(&mut self) -> FileNumber {
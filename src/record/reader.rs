@@ -13,6 +13,8 @@ pub struct RecordReader<R> {
     // This is useful, as it makes it possible to drop a record
     // if one of its fragment was corrupted.
     within_record: bool,
+    record_offset: u64,
+    end_offset: u64,
 }
 
 #[derive(Error, Debug)]
@@ -25,14 +27,48 @@ pub enum ReadRecordError {
 
 impl<R: AsyncRead + Unpin> RecordReader<R> {
     pub fn open(reader: R) -> Self {
-        let frame_reader = FrameReader::open(reader);
+        Self::with_frame_reader(FrameReader::open(reader))
+    }
+
+    /// Opens a reader starting at `offset`.
+    ///
+    /// See `FrameReader::open_at_offset`.
+    pub fn open_at_offset(reader: R, offset: u64) -> Self {
+        Self::with_frame_reader(FrameReader::open_at_offset(reader, offset))
+    }
+
+    fn with_frame_reader(frame_reader: FrameReader<R>) -> Self {
+        let offset = frame_reader.offset();
         RecordReader {
             frame_reader,
             record_buffer: Vec::with_capacity(10_000),
             within_record: false,
+            record_offset: offset,
+            end_offset: offset,
         }
     }
 
+    /// Returns the underlying reader.
+    ///
+    /// See `FrameReader::into_inner`.
+    pub fn into_inner(self) -> R {
+        self.frame_reader.into_inner()
+    }
+
+    /// Returns the offset of the end of the last complete record read.
+    ///
+    /// Once the reader is consumed, the bytes after this offset are either corrupted,
+    /// or a record that was only partially written.
+    pub fn end_offset(&self) -> u64 {
+        self.end_offset
+    }
+
+    /// Returns the offset of the last record read, or of the frame
+    /// that failed to be read.
+    pub fn offset(&self) -> u64 {
+        self.record_offset
+    }
+
     pub fn record<'a, S: Serializable<'a>>(&'a self) -> Option<S> {
         S::deserialize(&self.record_buffer)
     }
@@ -64,15 +100,18 @@ impl<R: AsyncRead + Unpin> RecordReader<R> {
                     if self.within_record {
                         self.record_buffer.extend_from_slice(frame_payload);
                     }
-                    if frame_type.is_last_frame_of_record() {
-                        if self.within_record {
-                            self.within_record = false;
-                            return Ok(true);
-                        }
+                    if frame_type.is_first_frame_of_record() {
+                        self.record_offset = self.frame_reader.frame_offset();
+                    }
+                    if frame_type.is_last_frame_of_record() && self.within_record {
+                        self.within_record = false;
+                        self.end_offset = self.frame_reader.offset();
+                        return Ok(true);
                     }
                 }
                 Err(ReadFrameError::Corruption) => {
                     self.within_record = false;
+                    self.record_offset = self.frame_reader.frame_offset();
                     return Err(ReadRecordError::Corruption);
                 }
                 Err(ReadFrameError::IoError(io_err)) => {
@@ -1,5 +1,3 @@
-use std::iter;
-
 use super::{ReadRecordError, RecordReader, RecordWriter};
 use crate::frame::{BLOCK_LEN, HEADER_LEN};
 
@@ -36,7 +34,7 @@ async fn test_simple_record() {
 }
 
 fn make_long_entry(len: usize) -> String {
-    iter::repeat('A').take(len).collect()
+    "A".repeat(len)
 }
 
 #[tokio::test]
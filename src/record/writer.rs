@@ -12,10 +12,10 @@ impl<W: io::AsyncWrite> RecordWriter<W> {}
 
 fn frame_type(is_first_frame: bool, is_last_frame: bool) -> FrameType {
     match (is_first_frame, is_last_frame) {
-        (true, true) => FrameType::FULL,
-        (true, false) => FrameType::FIRST,
-        (false, true) => FrameType::LAST,
-        (false, false) => FrameType::MIDDLE,
+        (true, true) => FrameType::Full,
+        (true, false) => FrameType::First,
+        (false, true) => FrameType::Last,
+        (false, false) => FrameType::Middle,
     }
 }
 
@@ -27,6 +27,17 @@ impl<W: io::AsyncWrite + Unpin> RecordWriter<W> {
             buffer: Vec::with_capacity(10_000),
         }
     }
+
+    /// Resumes writing after the `num_bytes` bytes already written by `wrt`.
+    ///
+    /// `num_bytes` must be the end of a record.
+    pub fn resume(wrt: W, num_bytes: u64) -> Self {
+        let frame_writer = FrameWriter::resume(wrt, num_bytes);
+        RecordWriter {
+            frame_writer,
+            buffer: Vec::with_capacity(10_000),
+        }
+    }
 }
 
 impl<W: AsyncWrite + Unpin> RecordWriter<W> {
@@ -0,0 +1,63 @@
+use crate::position::FileNumber;
+
+/// Defines how `MultiRecordLog::open` handles a corrupted log.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RecoveryMode {
+    /// Refuses to open a corrupted log, and returns `ReadRecordError::Corruption`.
+    #[default]
+    Strict,
+    /// Skips the corrupted frames and the records that are inconsistent with the
+    /// rest of the log, and lists them in a `RecoveryReport`.
+    ///
+    /// The corrupted data is left in the files, so the log needs to be opened in this
+    /// mode again until these files are removed.
+    BestEffort,
+}
+
+/// A part of the log skipped upon opening.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Corruption {
+    pub file_number: FileNumber,
+    /// Offset in the file of the corrupted frame, or of the inconsistent record.
+    pub offset: u64,
+    /// Queue of the inconsistent record. None if the record could not be read.
+    pub queue: Option<String>,
+    /// Number of records of the queue known to be lost.
+    pub num_records_lost: u64,
+}
+
+/// Lists the corruptions found upon opening a log in `RecoveryMode::BestEffort`.
+///
+/// The records of a corrupted frame cannot be attributed to a queue. They are only
+/// accounted for once a later record of the same queue reveals a gap in its positions.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RecoveryReport {
+    pub corruptions: Vec<Corruption>,
+}
+
+impl RecoveryReport {
+    /// Returns true if no corruption was found.
+    pub fn is_empty(&self) -> bool {
+        self.corruptions.is_empty()
+    }
+
+    /// Returns the number of records known to be lost, across all queues.
+    pub fn num_records_lost(&self) -> u64 {
+        self.corruptions
+            .iter()
+            .map(|corruption| corruption.num_records_lost)
+            .sum()
+    }
+
+    /// Returns the queues affected by a corruption, sorted and deduplicated.
+    pub fn queues(&self) -> Vec<&str> {
+        let mut queues: Vec<&str> = self
+            .corruptions
+            .iter()
+            .filter_map(|corruption| corruption.queue.as_deref())
+            .collect();
+        queues.sort_unstable();
+        queues.dedup();
+        queues
+    }
+}
@@ -17,19 +17,29 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::BTreeSet;
+use std::collections::BTreeMap;
 use std::io;
+use std::io::SeekFrom;
 use std::ops::RangeTo;
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::sync::Arc;
 
-use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
 use crate::position::FileNumber;
+use crate::storage::{LocalStorage, Storage, StorageFile};
+
+const CHECKPOINT_FILENAME: &str = "checkpoint";
+const CHECKPOINT_TMP_FILENAME: &str = "checkpoint.tmp";
 
 pub struct Directory {
-    dir: PathBuf,
-    // First position in files.
-    file_set: BTreeSet<FileNumber>,
+    storage: Arc<dyn Storage>,
+    // Number of bytes of each file.
+    // The size of the file being written is only set once it is complete.
+    files: BTreeMap<FileNumber, u64>,
+    // Number of bytes of the checkpoint file, and of the temporary file it is written to.
+    checkpoint_num_bytes: u64,
+    checkpoint_tmp_num_bytes: u64,
 }
 
 fn filename_to_position(file_name: &str) -> Option<FileNumber> {
@@ -40,87 +50,182 @@ fn filename_to_position(file_name: &str) -> Option<FileNumber> {
         return None;
     }
     let seq_number_str = &file_name[4..];
-    if !seq_number_str
-        .as_bytes()
-        .iter()
-        .all(|b| (b'0'..=b'9').contains(b))
-    {
+    if !seq_number_str.as_bytes().iter().all(|b| b.is_ascii_digit()) {
         return None;
     }
     let global_pos = file_name[4..].parse::<u32>().ok()?;
     Some(FileNumber::from(global_pos))
 }
 
+/// Returns the name of the log file `file_number` in the storage.
+pub(crate) fn filename(file_number: FileNumber) -> String {
+    format!("wal-{file_number}")
+}
+
+fn ignore_not_found(io_result: io::Result<()>) -> io::Result<()> {
+    match io_result {
+        Err(io_error) if io_error.kind() == io::ErrorKind::NotFound => Ok(()),
+        io_result => io_result,
+    }
+}
+
 impl Directory {
+    /// Opens the directory, and locks it.
+    ///
+    /// Fails with an `io::ErrorKind::WouldBlock` error if the directory is already
+    /// locked, by this process or by another one.
     pub async fn open(dir_path: &Path) -> io::Result<Directory> {
-        let mut seq_numbers: BTreeSet<FileNumber> = Default::default();
-        let mut read_dir = tokio::fs::read_dir(dir_path).await?;
-        while let Some(dir_entry) = read_dir.next_entry().await? {
-            if !dir_entry.file_type().await?.is_file() {
-                continue;
-            }
-            let file_name = if let Some(file_name) = dir_entry.file_name().to_str() {
-                file_name.to_string()
-            } else {
-                continue;
-            };
+        let storage = LocalStorage::open(dir_path).await?;
+        Self::open_with_storage(Arc::new(storage)).await
+    }
+
+    /// Opens the log files of the given storage.
+    pub async fn open_with_storage(storage: Arc<dyn Storage>) -> io::Result<Directory> {
+        let mut files: BTreeMap<FileNumber, u64> = Default::default();
+        let mut checkpoint_num_bytes = 0;
+        let mut checkpoint_tmp_num_bytes = 0;
+        for (file_name, num_bytes) in storage.list().await? {
             if let Some(seq_number) = filename_to_position(&file_name) {
-                seq_numbers.insert(seq_number);
+                files.insert(seq_number, num_bytes);
+            } else if file_name == CHECKPOINT_FILENAME {
+                checkpoint_num_bytes = num_bytes;
+            } else if file_name == CHECKPOINT_TMP_FILENAME {
+                checkpoint_tmp_num_bytes = num_bytes;
             }
         }
         Ok(Directory {
-            dir: dir_path.to_path_buf(),
-            file_set: seq_numbers,
+            storage,
+            files,
+            checkpoint_num_bytes,
+            checkpoint_tmp_num_bytes,
         })
     }
 
+    pub fn storage(&self) -> &Arc<dyn Storage> {
+        &self.storage
+    }
+
     pub fn num_files(&self) -> usize {
-        self.file_set.len()
+        self.files.len()
     }
 
-    pub async fn remove_files(&mut self, file_to_remove: RangeTo<FileNumber>) -> io::Result<()> {
-        let mut removed_file_numbers = Vec::new();
-        for &file_number in self.file_set.range(file_to_remove) {
-            let filepath = self.filepath(file_number);
-            tokio::fs::remove_file(&filepath).await?;
-            removed_file_numbers.push(file_number);
+    /// Returns the total number of bytes of the complete files, including the checkpoint.
+    pub fn num_bytes(&self) -> u64 {
+        self.files.values().sum::<u64>() + self.checkpoint_num_bytes + self.checkpoint_tmp_num_bytes
+    }
+
+    /// Returns the number of bytes of a file, if it exists.
+    pub fn file_num_bytes(&self, file_number: FileNumber) -> Option<u64> {
+        self.files.get(&file_number).copied()
+    }
+
+    /// Sets the number of bytes of a file, once it is complete.
+    pub fn set_file_num_bytes(&mut self, file_number: FileNumber, num_bytes: u64) {
+        if let Some(file_num_bytes) = self.files.get_mut(&file_number) {
+            *file_num_bytes = num_bytes;
         }
-        for file_number in removed_file_numbers {
-            self.file_set.remove(&file_number);
+    }
+
+    /// Removes the files in the given range.
+    ///
+    /// If an error occurs, the files removed so far are not part
+    /// of the directory anymore, and the removal can be safely retried.
+    pub async fn remove_files(&mut self, file_to_remove: RangeTo<FileNumber>) -> io::Result<()> {
+        let file_numbers_to_remove: Vec<FileNumber> = self
+            .files
+            .range(file_to_remove)
+            .map(|(file_number, _)| *file_number)
+            .collect();
+        for file_number in file_numbers_to_remove {
+            ignore_not_found(self.storage.remove(&filename(file_number)).await)?;
+            self.files.remove(&file_number);
         }
         Ok(())
     }
 
     pub fn file_numbers<'a>(&'a self) -> impl Iterator<Item = FileNumber> + 'a {
-        self.file_set.iter().copied()
-    }
-
-    fn filepath(&self, seq_number: FileNumber) -> PathBuf {
-        self.dir.join(&format!("wal-{seq_number}"))
+        self.files.keys().copied()
     }
 
     pub fn last_file_number(&self) -> FileNumber {
-        self.file_set.iter().last().copied().unwrap_or_default()
+        self.files.keys().last().copied().unwrap_or_default()
     }
 
-    pub async fn new_file(&mut self) -> io::Result<File> {
+    pub async fn new_file(&mut self) -> io::Result<Box<dyn StorageFile>> {
         let mut file_number = self.last_file_number();
         file_number.inc();
-        self.file_set.insert(file_number);
-        let new_filepath = self.filepath(file_number);
-        let file = OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .open(&new_filepath)
-            .await?;
+        let file = self.storage.create(&filename(file_number)).await?;
+        self.files.insert(file_number, 0);
         Ok(file)
     }
 
-    pub async fn open_file(&mut self, file_number: FileNumber) -> io::Result<File> {
-        let filepath = self.filepath(file_number);
-        let file = OpenOptions::new().read(true).open(&filepath).await?;
+    /// Syncs the directory itself, so that the creation, the removal and the renaming
+    /// of its files are durable.
+    pub async fn sync(&self) -> io::Result<()> {
+        self.storage.sync().await
+    }
+
+    /// Truncates a file to `num_bytes`, and syncs it.
+    pub async fn truncate_file(
+        &mut self,
+        file_number: FileNumber,
+        num_bytes: u64,
+    ) -> io::Result<()> {
+        let mut file = self.storage.open(&filename(file_number)).await?;
+        file.set_len(num_bytes).await?;
+        file.sync_all().await?;
+        self.set_file_num_bytes(file_number, num_bytes);
+        Ok(())
+    }
+
+    /// Opens an existing file, to append to it.
+    pub async fn open_file_for_append(
+        &mut self,
+        file_number: FileNumber,
+    ) -> io::Result<Box<dyn StorageFile>> {
+        let mut file = self.storage.open(&filename(file_number)).await?;
+        file.seek(SeekFrom::End(0)).await?;
         Ok(file)
     }
+
+    /// Replaces the checkpoint file.
+    ///
+    /// The checkpoint is written to a temporary file first, so that a crash
+    /// never leaves a partially written checkpoint behind.
+    pub async fn write_checkpoint(&mut self, checkpoint: &[u8]) -> io::Result<()> {
+        // A temporary file may be left behind by a failed write.
+        ignore_not_found(self.storage.remove(CHECKPOINT_TMP_FILENAME).await)?;
+        self.checkpoint_tmp_num_bytes = 0;
+        let mut file = self.storage.create(CHECKPOINT_TMP_FILENAME).await?;
+        // If the write fails, the temporary file holds at most the whole checkpoint.
+        self.checkpoint_tmp_num_bytes = checkpoint.len() as u64;
+        file.write_all(checkpoint).await?;
+        file.flush().await?;
+        file.sync_all().await?;
+        self.storage
+            .rename(CHECKPOINT_TMP_FILENAME, CHECKPOINT_FILENAME)
+            .await?;
+        self.checkpoint_num_bytes = checkpoint.len() as u64;
+        self.checkpoint_tmp_num_bytes = 0;
+        self.sync().await?;
+        Ok(())
+    }
+
+    /// Returns the content of the checkpoint file, or None if there is none.
+    pub async fn read_checkpoint(&self) -> io::Result<Option<Vec<u8>>> {
+        let mut file = match self.storage.open(CHECKPOINT_FILENAME).await {
+            Ok(file) => file,
+            Err(io_error) if io_error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(io_error) => return Err(io_error),
+        };
+        let mut checkpoint = Vec::new();
+        file.read_to_end(&mut checkpoint).await?;
+        Ok(Some(checkpoint))
+    }
+
+    pub async fn open_file(&mut self, file_number: FileNumber) -> io::Result<Box<dyn StorageFile>> {
+        self.storage.open(&filename(file_number)).await
+    }
 }
 
 #[cfg(test)]
@@ -160,6 +265,16 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_directory_locked() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let directory = Directory::open(tmp_dir.path()).await.unwrap();
+        let lock_error = Directory::open(tmp_dir.path()).await.err().unwrap();
+        assert_eq!(lock_error.kind(), io::ErrorKind::WouldBlock);
+        drop(directory);
+        Directory::open(tmp_dir.path()).await.unwrap();
+    }
+
     #[tokio::test]
     async fn test_directory() {
         let tmp_dir = tempfile::tempdir().unwrap();
@@ -181,7 +296,45 @@ mod tests {
             let directory = Directory::open(tmp_dir.path()).await.unwrap();
             let filepaths: Vec<FileNumber> = directory.file_numbers().collect();
             assert_eq!(&filepaths, &[1.into(), 2.into()]);
+            assert_eq!(directory.num_bytes(), 11);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_directory_new_file_failure() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut directory = Directory::open(tmp_dir.path()).await.unwrap();
+        directory.new_file().await.unwrap();
+        // A directory in the way of the next file makes its creation fail.
+        let file_path = tmp_dir.path().join(filename(2.into()));
+        std::fs::create_dir(&file_path).unwrap();
+        assert!(directory.new_file().await.is_err());
+        let file_numbers: Vec<FileNumber> = directory.file_numbers().collect();
+        assert_eq!(&file_numbers, &[1.into()]);
+        std::fs::remove_dir(&file_path).unwrap();
+        directory.new_file().await.unwrap();
+        let file_numbers: Vec<FileNumber> = directory.file_numbers().collect();
+        assert_eq!(&file_numbers, &[1.into(), 2.into()]);
+    }
+
+    #[tokio::test]
+    async fn test_directory_num_bytes_checkpoint() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        {
+            let mut directory = Directory::open(tmp_dir.path()).await.unwrap();
+            let mut file = directory.new_file().await.unwrap();
+            file.write_all(b"hello").await.unwrap();
+            file.flush().await.unwrap();
+            directory.set_file_num_bytes(1.into(), 5);
+            directory.write_checkpoint(b"checkpoint").await.unwrap();
+            assert_eq!(directory.num_bytes(), 15);
+            directory.write_checkpoint(b"new").await.unwrap();
+            assert_eq!(directory.num_bytes(), 8);
         }
+        // A temporary file left behind by a failed checkpoint is accounted for.
+        std::fs::write(tmp_dir.path().join(CHECKPOINT_TMP_FILENAME), b"partial").unwrap();
+        let directory = Directory::open(tmp_dir.path()).await.unwrap();
+        assert_eq!(directory.num_bytes(), 15);
     }
 
     #[tokio::test]
@@ -211,6 +364,7 @@ mod tests {
         {
             let mut directory = Directory::open(tmp_dir.path()).await.unwrap();
             directory.remove_files(..FileNumber::from(2)).await.unwrap();
+            directory.sync().await.unwrap();
             let file_numbers: Vec<FileNumber> = directory.file_numbers().collect();
             assert_eq!(&file_numbers, &[2.into()]);
         }
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::io::{self, SeekFrom};
+use std::sync::Arc;
+
+use tokio::io::AsyncSeekExt;
+
+use crate::position::FileNumber;
+use crate::record::{ReadRecordError, RecordReader};
+use crate::rolling::directory::filename;
+use crate::rolling::Record;
+use crate::storage::{Storage, StorageFile};
+
+// Beyond this number of bytes, the file is read again from the offset of the record,
+// rather than read through from the last record read.
+const MAX_NUM_BYTES_SKIPPED: u64 = 1 << 20;
+
+/// Reads back the payloads of the records appended to the log, given the file
+/// and the offset they were written at.
+///
+/// This serves the records spilled from the in-memory queues. Each file is opened
+/// once, and kept open as long as the reader is alive.
+pub(crate) struct PayloadReader {
+    // None if the log files are unknown, in which case no payload can be read.
+    storage_opt: Option<Arc<dyn Storage>>,
+    // Files opened, except the one being read.
+    files: HashMap<FileNumber, Box<dyn StorageFile>>,
+    // Reader of the last file read, positioned after the last record read.
+    reader_opt: Option<(FileNumber, RecordReader<Box<dyn StorageFile>>)>,
+}
+
+impl PayloadReader {
+    pub fn new(storage_opt: Option<Arc<dyn Storage>>) -> Self {
+        PayloadReader {
+            storage_opt,
+            files: HashMap::new(),
+            reader_opt: None,
+        }
+    }
+
+    async fn take_or_open_file(
+        &mut self,
+        file_number: FileNumber,
+    ) -> io::Result<Box<dyn StorageFile>> {
+        if let Some(file) = self.files.remove(&file_number) {
+            return Ok(file);
+        }
+        let storage = self.storage_opt.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "the storage of the log is unknown: spilled records cannot be read back",
+            )
+        })?;
+        storage.open(&filename(file_number)).await
+    }
+
+    /// Positions the reader at `offset` in `file_number`, keeping the file
+    /// previously read open.
+    async fn seek(&mut self, file_number: FileNumber, offset: u64) -> io::Result<()> {
+        let mut file = match self.reader_opt.take() {
+            Some((reader_file_number, record_reader)) if reader_file_number == file_number => {
+                record_reader.into_inner()
+            }
+            Some((reader_file_number, record_reader)) => {
+                self.files
+                    .insert(reader_file_number, record_reader.into_inner());
+                self.take_or_open_file(file_number).await?
+            }
+            None => self.take_or_open_file(file_number).await?,
+        };
+        file.seek(SeekFrom::Start(offset)).await?;
+        let record_reader = RecordReader::open_at_offset(file, offset);
+        self.reader_opt = Some((file_number, record_reader));
+        Ok(())
+    }
+
+    /// Reads the payload of the record appended at `position`, written to `file_number`
+    /// at `offset`.
+    ///
+    /// The records read in a row are expected to be in the order of the log, so that
+    /// nearby records are read through, without seeking.
+    pub async fn read_payload(
+        &mut self,
+        file_number: FileNumber,
+        offset: u64,
+        position: u64,
+    ) -> Result<Vec<u8>, ReadRecordError> {
+        let can_read_through = match &self.reader_opt {
+            Some((reader_file_number, record_reader)) => {
+                *reader_file_number == file_number
+                    && record_reader.end_offset() <= offset
+                    && offset - record_reader.end_offset() <= MAX_NUM_BYTES_SKIPPED
+            }
+            None => false,
+        };
+        if !can_read_through {
+            self.seek(file_number, offset).await?;
+        }
+        let read_res = self.read_next_payload(offset, position).await;
+        if read_res.is_err() {
+            // The reader is left in an unknown state: the next read seeks again.
+            if let Some((reader_file_number, record_reader)) = self.reader_opt.take() {
+                self.files
+                    .insert(reader_file_number, record_reader.into_inner());
+            }
+        }
+        read_res
+    }
+
+    async fn read_next_payload(
+        &mut self,
+        offset: u64,
+        position: u64,
+    ) -> Result<Vec<u8>, ReadRecordError> {
+        let (_, record_reader) = self
+            .reader_opt
+            .as_mut()
+            .expect("the reader should have been positioned");
+        loop {
+            if !record_reader.go_next().await? {
+                return Err(ReadRecordError::Corruption);
+            }
+            // The offset of a record may be followed by the padding of a block,
+            // so the record is the first one starting at or after this offset.
+            if record_reader.offset() < offset {
+                continue;
+            }
+            return match record_reader.record() {
+                Some(Record::AppendRecord {
+                    position: record_position,
+                    codec,
+                    payload,
+                    ..
+                }) if record_position == position => codec
+                    .decompress(payload)
+                    .map(|payload| payload.into_owned())
+                    .map_err(|_| ReadRecordError::Corruption),
+                _ => Err(ReadRecordError::Corruption),
+            };
+        }
+    }
+}
@@ -1,38 +1,116 @@
 use std::collections::VecDeque;
 use std::io;
+use std::io::SeekFrom;
 use std::path::Path;
+use std::sync::Arc;
 
-use tokio::fs::File;
+use tokio::io::AsyncSeekExt;
 
 use crate::position::FileNumber;
 use crate::record::{ReadRecordError, RecordReader};
 use crate::rolling::record::Record;
-use crate::rolling::{Directory, RecordLogWriter};
+use crate::rolling::{Directory, RecordLogWriter, RolloverPolicy, SyncPolicy};
+use crate::storage::{Storage, StorageFile};
 
 pub struct RecordLogReader {
     directory: Directory,
     file_numbers: VecDeque<FileNumber>,
-    reader_opt: Option<(FileNumber, RecordReader<File>)>,
+    reader_opt: Option<(FileNumber, RecordReader<Box<dyn StorageFile>>)>,
 }
 
 impl RecordLogReader {
     pub async fn open(dir_path: &Path) -> io::Result<Self> {
         let directory = Directory::open(dir_path).await?;
+        Ok(Self::from_directory(directory))
+    }
+
+    pub async fn open_with_storage(storage: Arc<dyn Storage>) -> io::Result<Self> {
+        let directory = Directory::open_with_storage(storage).await?;
+        Ok(Self::from_directory(directory))
+    }
+
+    fn from_directory(directory: Directory) -> Self {
         let file_numbers = directory.file_numbers().collect();
-        Ok(RecordLogReader {
+        RecordLogReader {
             file_numbers,
             directory,
             reader_opt: None,
-        })
+        }
+    }
+
+    pub fn directory(&self) -> &Directory {
+        &self.directory
+    }
+
+    /// Returns the content of the checkpoint file, or None if there is none.
+    pub async fn read_checkpoint(&self) -> io::Result<Option<Vec<u8>>> {
+        self.directory.read_checkpoint().await
+    }
+
+    /// Skips the files before `file_number`, and the first `offset` bytes of this file.
+    ///
+    /// `offset` must be the offset of the beginning of a record, and this should only
+    /// be called before reading any record.
+    pub async fn seek(&mut self, file_number: FileNumber, offset: u64) -> io::Result<()> {
+        assert!(
+            self.reader_opt.is_none(),
+            "`seek` should only be called before reading any record"
+        );
+        while let Some(next_file_number) = self.file_numbers.pop_front() {
+            if next_file_number < file_number {
+                continue;
+            }
+            if next_file_number > file_number {
+                self.file_numbers.push_front(next_file_number);
+                break;
+            }
+            let mut file = self.directory.open_file(file_number).await?;
+            file.seek(SeekFrom::Start(offset)).await?;
+            let record_reader = RecordReader::open_at_offset(file, offset);
+            self.reader_opt = Some((file_number, record_reader));
+            break;
+        }
+        Ok(())
     }
 
     /// `into_writer` should only be called after the reader has been entirely consumed.
-    pub async fn into_writer(mut self) -> Result<RecordLogWriter, ReadRecordError> {
+    ///
+    /// The bytes following the last complete record of the last file, left by a crash
+    /// in the middle of a write, are truncated. If `resume_last_file` is true, the writer
+    /// then appends to the last file rather than to a new file.
+    pub async fn into_writer(
+        mut self,
+        sync_policy: SyncPolicy,
+        rollover_policy: RolloverPolicy,
+        resume_last_file: bool,
+    ) -> Result<RecordLogWriter, ReadRecordError> {
         assert!(
             !self.go_next_record().await?,
             "`into_writer` should only be called after the reader has been entirely consumed"
         );
-        Ok(RecordLogWriter::open(self.directory.into()))
+        let last_file_opt = self
+            .reader_opt
+            .take()
+            .map(|(file_number, record_reader)| (file_number, record_reader.end_offset()));
+        if let Some((last_file_number, end_offset)) = last_file_opt {
+            let file_num_bytes = self
+                .directory
+                .file_num_bytes(last_file_number)
+                .unwrap_or_default();
+            if file_num_bytes > end_offset {
+                self.directory
+                    .truncate_file(last_file_number, end_offset)
+                    .await?;
+            }
+        }
+        let mut record_log_writer =
+            RecordLogWriter::open(self.directory, sync_policy, rollover_policy);
+        if let Some((_, end_offset)) = last_file_opt {
+            if resume_last_file {
+                record_log_writer.resume_last_file(end_offset).await?;
+            }
+        }
+        Ok(record_log_writer)
     }
 
     async fn go_next_record_current_reader(&mut self) -> Result<bool, ReadRecordError> {
@@ -65,13 +143,32 @@ impl RecordLogReader {
         }
     }
 
+    /// Returns the file and the offset of the last record read, or of the corruption
+    /// that prevented reading it.
+    pub fn position(&self) -> Option<(FileNumber, u64)> {
+        let (file_number, record_reader) = self.reader_opt.as_ref()?;
+        Some((*file_number, record_reader.offset()))
+    }
+
+    #[cfg(test)]
     pub(crate) async fn read_record<'a>(
         &'a mut self,
     ) -> Result<Option<(FileNumber, Record<'a>)>, ReadRecordError> {
+        let record_opt = self
+            .read_record_with_offset()
+            .await?
+            .map(|(file_number, _, record)| (file_number, record));
+        Ok(record_opt)
+    }
+
+    /// Same as `read_record`, but also returns the offset of the record in its file.
+    pub(crate) async fn read_record_with_offset<'a>(
+        &'a mut self,
+    ) -> Result<Option<(FileNumber, u64, Record<'a>)>, ReadRecordError> {
         if self.go_next_record().await? {
             let (file_number, record_reader) = self.reader_opt.as_ref().unwrap();
             let record: Record<'a> = record_reader.record().ok_or(ReadRecordError::Corruption)?;
-            Ok(Some((*file_number, record)))
+            Ok(Some((*file_number, record_reader.offset(), record)))
         } else {
             Ok(None)
         }
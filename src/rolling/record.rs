@@ -1,13 +1,22 @@
 use std::convert::{TryFrom, TryInto};
 
+use crate::compression::Codec;
 use crate::record::Serializable;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Record<'a> {
     /// Adds a new record to a specific queue.
+    ///
+    /// The timestamp, if any, is serialized before the payload, under a different
+    /// record type, so that records without timestamp keep the same format.
+    ///
+    /// `payload` is compressed with `codec`. Compressed records are serialized under
+    /// yet another record type, so that uncompressed records keep the same format.
     AppendRecord {
         position: u64,
         queue: &'a str,
+        timestamp: Option<u64>,
+        codec: Codec,
         payload: &'a [u8],
     },
     /// Records the truncation of a specific queue.
@@ -17,6 +26,16 @@ pub enum Record<'a> {
     ///
     /// `position` is the position of the NEXT message to be appended.
     Touch { position: u64, queue: &'a str },
+    /// Records the deletion of a queue, and of all of its records.
+    DeleteQueue { queue: &'a str },
+    /// Starts the rewrite of all of the records of a queue by a compaction.
+    ///
+    /// `position` is the position of the first record of the queue. The records
+    /// that follow, up to the matching `CompactionEnd`, replace the records of the queue.
+    /// A compaction without `CompactionEnd` was interrupted and is ignored.
+    CompactionStart { position: u64, queue: &'a str },
+    /// Ends the rewrite of a queue started by `CompactionStart`.
+    CompactionEnd { queue: &'a str },
 }
 
 #[repr(u8)]
@@ -25,6 +44,11 @@ enum RecordType {
     AppendRecord = 0,
     Truncate = 1,
     Touch = 2,
+    DeleteQueue = 3,
+    CompactionStart = 4,
+    CompactionEnd = 5,
+    AppendRecordWithTimestamp = 6,
+    CompressedAppendRecord = 7,
 }
 
 impl TryFrom<u8> for RecordType {
@@ -35,6 +59,11 @@ impl TryFrom<u8> for RecordType {
             0 => Ok(RecordType::AppendRecord),
             1 => Ok(RecordType::Truncate),
             2 => Ok(RecordType::Touch),
+            3 => Ok(RecordType::DeleteQueue),
+            4 => Ok(RecordType::CompactionStart),
+            5 => Ok(RecordType::CompactionEnd),
+            6 => Ok(RecordType::AppendRecordWithTimestamp),
+            7 => Ok(RecordType::CompressedAppendRecord),
             _ => Err(()),
         }
     }
@@ -62,36 +91,128 @@ impl<'a> Serializable<'a> for Record<'a> {
             Record::AppendRecord {
                 position,
                 queue,
+                timestamp: None,
+                codec: Codec::None,
                 payload,
             } => {
                 serialize(RecordType::AppendRecord, position, queue, payload, buffer);
             }
+            Record::AppendRecord {
+                position,
+                queue,
+                timestamp: Some(timestamp),
+                codec: Codec::None,
+                payload,
+            } => {
+                serialize(
+                    RecordType::AppendRecordWithTimestamp,
+                    position,
+                    queue,
+                    &timestamp.to_le_bytes(),
+                    buffer,
+                );
+                buffer.extend_from_slice(payload);
+            }
+            Record::AppendRecord {
+                position,
+                queue,
+                timestamp,
+                codec,
+                payload,
+            } => {
+                // The codec, then whether the record has a timestamp.
+                let flags = [codec as u8, timestamp.is_some() as u8];
+                serialize(
+                    RecordType::CompressedAppendRecord,
+                    position,
+                    queue,
+                    &flags,
+                    buffer,
+                );
+                if let Some(timestamp) = timestamp {
+                    buffer.extend_from_slice(&timestamp.to_le_bytes());
+                }
+                buffer.extend_from_slice(payload);
+            }
             Record::Truncate { queue, position } => {
                 serialize(RecordType::Truncate, position, queue, &[], buffer);
             }
             Record::Touch { queue, position } => {
                 serialize(RecordType::Touch, position, queue, &[], buffer);
             }
+            Record::DeleteQueue { queue } => {
+                serialize(RecordType::DeleteQueue, 0, queue, &[], buffer);
+            }
+            Record::CompactionStart { queue, position } => {
+                serialize(RecordType::CompactionStart, position, queue, &[], buffer);
+            }
+            Record::CompactionEnd { queue } => {
+                serialize(RecordType::CompactionEnd, 0, queue, &[], buffer);
+            }
         }
     }
 
     fn deserialize(buffer: &'a [u8]) -> Option<Record<'a>> {
-        let enum_tag = RecordType::try_from(buffer[0]).ok()?;
-        if buffer.len() < 8 {
+        // The record type, the position and the length of the queue name.
+        const HEADER_LEN: usize = 1 + 8 + 2;
+        if buffer.len() < HEADER_LEN {
             return None;
         }
+        let enum_tag = RecordType::try_from(buffer[0]).ok()?;
         let position = u64::from_le_bytes(buffer[1..9].try_into().unwrap());
         let queue_len = u16::from_le_bytes(buffer[9..11].try_into().unwrap()) as usize;
-        let queue = std::str::from_utf8(&buffer[11..][..queue_len]).ok()?;
-        let payload = &buffer[11 + queue_len..];
+        if buffer.len() < HEADER_LEN + queue_len {
+            return None;
+        }
+        let queue = std::str::from_utf8(&buffer[HEADER_LEN..][..queue_len]).ok()?;
+        let payload = &buffer[HEADER_LEN + queue_len..];
         match enum_tag {
             RecordType::AppendRecord => Some(Record::AppendRecord {
                 position,
                 queue,
+                timestamp: None,
+                codec: Codec::None,
                 payload,
             }),
+            RecordType::AppendRecordWithTimestamp => {
+                if payload.len() < 8 {
+                    return None;
+                }
+                let (timestamp_bytes, payload) = payload.split_at(8);
+                Some(Record::AppendRecord {
+                    position,
+                    queue,
+                    timestamp: Some(u64::from_le_bytes(timestamp_bytes.try_into().unwrap())),
+                    codec: Codec::None,
+                    payload,
+                })
+            }
+            RecordType::CompressedAppendRecord => {
+                if payload.len() < 2 {
+                    return None;
+                }
+                let codec = Codec::try_from(payload[0]).ok()?;
+                let (timestamp, payload) = match payload[1] {
+                    0 => (None, &payload[2..]),
+                    1 if payload.len() >= 10 => {
+                        let timestamp = u64::from_le_bytes(payload[2..10].try_into().unwrap());
+                        (Some(timestamp), &payload[10..])
+                    }
+                    _ => return None,
+                };
+                Some(Record::AppendRecord {
+                    position,
+                    queue,
+                    timestamp,
+                    codec,
+                    payload,
+                })
+            }
             RecordType::Truncate => Some(Record::Truncate { position, queue }),
             RecordType::Touch => Some(Record::Touch { position, queue }),
+            RecordType::DeleteQueue => Some(Record::DeleteQueue { queue }),
+            RecordType::CompactionStart => Some(Record::CompactionStart { position, queue }),
+            RecordType::CompactionEnd => Some(Record::CompactionEnd { queue }),
         }
     }
 }
@@ -100,7 +221,9 @@ impl<'a> Serializable<'a> for Record<'a> {
 mod tests {
     use std::convert::TryFrom;
 
-    use crate::rolling::record::RecordType;
+    use crate::compression::Codec;
+    use crate::record::Serializable;
+    use crate::rolling::record::{Record, RecordType};
 
     #[test]
     fn test_record_type_serialize() {
@@ -111,6 +234,89 @@ mod tests {
                 num_record_types += 1;
             }
         }
-        assert_eq!(num_record_types, 3);
+        assert_eq!(num_record_types, 8);
+    }
+
+    #[test]
+    fn test_record_serialized_format() {
+        // Records are written with this hand-rolled format, rather than with serde.
+        let mut buffer = Vec::new();
+        Record::AppendRecord {
+            position: 3,
+            queue: "queue",
+            timestamp: None,
+            codec: Codec::None,
+            payload: b"hello",
+        }
+        .serialize(&mut buffer);
+        assert_eq!(
+            buffer,
+            b"\x00\x03\x00\x00\x00\x00\x00\x00\x00\x05\x00queuehello"
+        );
+        Record::Truncate {
+            position: 258,
+            queue: "q",
+        }
+        .serialize(&mut buffer);
+        assert_eq!(buffer, b"\x01\x02\x01\x00\x00\x00\x00\x00\x00\x01\x00q");
+    }
+
+    #[test]
+    fn test_record_append_record_timestamp() {
+        let mut buffer = Vec::new();
+        for timestamp in [None, Some(1_650_000_000_000)] {
+            for codec in [Codec::None, Codec::Lz4, Codec::Zstd] {
+                let record = Record::AppendRecord {
+                    position: 3,
+                    queue: "queue",
+                    timestamp,
+                    codec,
+                    payload: b"hello",
+                };
+                record.serialize(&mut buffer);
+                // Uncompressed records keep the format they had before compression.
+                let expected_record_type = match (codec, timestamp) {
+                    (Codec::None, None) => RecordType::AppendRecord,
+                    (Codec::None, Some(_)) => RecordType::AppendRecordWithTimestamp,
+                    _ => RecordType::CompressedAppendRecord,
+                };
+                assert_eq!(buffer[0], expected_record_type as u8);
+                assert_eq!(Record::deserialize(&buffer), Some(record));
+            }
+        }
+    }
+
+    #[test]
+    fn test_record_deserialize_truncated() {
+        let mut buffer = Vec::new();
+        Record::Truncate {
+            queue: "queue",
+            position: 3,
+        }
+        .serialize(&mut buffer);
+        assert_eq!(buffer.len(), 16);
+        for len in 0..buffer.len() {
+            assert_eq!(Record::deserialize(&buffer[..len]), None);
+        }
+        assert!(Record::deserialize(&buffer).is_some());
+    }
+
+    #[test]
+    fn test_record_deserialize_oversized_queue_len() {
+        let mut buffer = Vec::new();
+        Record::AppendRecord {
+            position: 3,
+            queue: "queue",
+            timestamp: None,
+            codec: Codec::None,
+            payload: b"hello",
+        }
+        .serialize(&mut buffer);
+        // The queue name now claims more bytes than the record holds.
+        let oversized_queue_len = buffer.len() as u16;
+        buffer[9..11].copy_from_slice(&oversized_queue_len.to_le_bytes());
+        assert_eq!(Record::deserialize(&buffer), None);
+        buffer[9..11].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert_eq!(Record::deserialize(&buffer), None);
     }
 }
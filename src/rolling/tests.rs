@@ -20,9 +20,10 @@
 
 use tempfile::tempdir;
 
+use crate::compression::Codec;
 use crate::position::FileNumber;
 use crate::rolling::record::Record;
-use crate::rolling::RecordLogReader;
+use crate::rolling::{RecordLogReader, RolloverPolicy, SyncPolicy};
 
 #[tokio::test]
 async fn test_record_log_reader_empty() {
@@ -37,27 +38,37 @@ async fn test_record_log_reader_simple() {
     let record1 = Record::AppendRecord {
         position: 0,
         queue: "queue",
+        timestamp: None,
+        codec: Codec::None,
         payload: b"hello0",
     };
     let record2 = Record::AppendRecord {
         position: 1,
         queue: "queue",
+        timestamp: None,
+        codec: Codec::None,
         payload: b"hello1",
     };
     let record3 = Record::AppendRecord {
         position: 2,
         queue: "queue",
+        timestamp: None,
+        codec: Codec::None,
         payload: b"hello2",
     };
     {
         let mut record_log_reader = RecordLogReader::open(tempdir.path()).await.unwrap();
         assert!(record_log_reader.read_record().await.unwrap().is_none());
-        let mut record_log_writer = record_log_reader.into_writer().await.unwrap();
+        let mut record_log_writer = record_log_reader
+            .into_writer(SyncPolicy::default(), RolloverPolicy::default(), false)
+            .await
+            .unwrap();
         assert_eq!(record_log_writer.roll_if_needed().await.unwrap(), 1.into());
         record_log_writer.write_record(record1).await.unwrap();
         assert_eq!(record_log_writer.roll_if_needed().await.unwrap(), 1.into());
         record_log_writer.write_record(record2).await.unwrap();
         record_log_writer.flush().await.unwrap();
+        drop(record_log_writer);
         let mut record_log_reader = RecordLogReader::open(tempdir.path()).await.unwrap();
         assert_eq!(
             record_log_reader.read_record().await.unwrap(),
@@ -67,7 +78,10 @@ async fn test_record_log_reader_simple() {
             record_log_reader.read_record().await.unwrap(),
             Some((FileNumber::from(1u32), record2))
         );
-        let mut record_log_writer = record_log_reader.into_writer().await.unwrap();
+        let mut record_log_writer = record_log_reader
+            .into_writer(SyncPolicy::default(), RolloverPolicy::default(), false)
+            .await
+            .unwrap();
         assert_eq!(record_log_writer.roll_if_needed().await.unwrap(), 2.into());
         record_log_writer.flush().await.unwrap()
     }
@@ -81,7 +95,10 @@ async fn test_record_log_reader_simple() {
             record_log_reader.read_record().await.unwrap(),
             Some((FileNumber::from(1u32), record2))
         );
-        let mut record_log_writer = record_log_reader.into_writer().await.unwrap();
+        let mut record_log_writer = record_log_reader
+            .into_writer(SyncPolicy::default(), RolloverPolicy::default(), false)
+            .await
+            .unwrap();
         assert_eq!(record_log_writer.roll_if_needed().await.unwrap(), 3.into());
         record_log_writer.write_record(record3).await.unwrap();
         record_log_writer.flush().await.unwrap()
@@ -102,3 +119,81 @@ async fn test_record_log_reader_simple() {
         );
     }
 }
+
+#[tokio::test]
+async fn test_record_log_torn_tail_truncated() {
+    let tempdir = tempdir().unwrap();
+    // The large records span several blocks.
+    let large_payload = vec![1u8; 40_000];
+    let record1 = Record::AppendRecord {
+        position: 0,
+        queue: "queue",
+        timestamp: None,
+        codec: Codec::None,
+        payload: &large_payload,
+    };
+    let record2 = Record::AppendRecord {
+        position: 1,
+        queue: "queue",
+        timestamp: None,
+        codec: Codec::None,
+        payload: b"hello",
+    };
+    let record3 = Record::AppendRecord {
+        position: 2,
+        queue: "queue",
+        timestamp: None,
+        codec: Codec::None,
+        payload: &large_payload,
+    };
+    let filepath = tempdir.path().join("wal-00000000000000000001");
+    let num_bytes = {
+        let record_log_reader = RecordLogReader::open(tempdir.path()).await.unwrap();
+        let mut record_log_writer = record_log_reader
+            .into_writer(SyncPolicy::default(), RolloverPolicy::default(), false)
+            .await
+            .unwrap();
+        record_log_writer.roll_if_needed().await.unwrap();
+        record_log_writer.write_record(record1).await.unwrap();
+        record_log_writer.write_record(record2).await.unwrap();
+        record_log_writer.flush().await.unwrap();
+        let num_bytes = std::fs::metadata(&filepath).unwrap().len();
+        record_log_writer.write_record(record3).await.unwrap();
+        record_log_writer.flush().await.unwrap();
+        num_bytes
+    };
+    // Simulates a crash in the middle of the write of the last record.
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&filepath)
+        .unwrap();
+    file.set_len(num_bytes + 20_000).unwrap();
+    {
+        let mut record_log_reader = RecordLogReader::open(tempdir.path()).await.unwrap();
+        assert_eq!(
+            record_log_reader.read_record().await.unwrap(),
+            Some((FileNumber::from(1u32), record1))
+        );
+        assert_eq!(
+            record_log_reader.read_record().await.unwrap(),
+            Some((FileNumber::from(1u32), record2))
+        );
+        assert!(record_log_reader.read_record().await.unwrap().is_none());
+        let mut record_log_writer = record_log_reader
+            .into_writer(SyncPolicy::default(), RolloverPolicy::default(), true)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::metadata(&filepath).unwrap().len(), num_bytes);
+        assert_eq!(record_log_writer.roll_if_needed().await.unwrap(), 1.into());
+        record_log_writer.write_record(record3).await.unwrap();
+        record_log_writer.flush().await.unwrap();
+    }
+    let mut record_log_reader = RecordLogReader::open(tempdir.path()).await.unwrap();
+    for record in [record1, record2, record3] {
+        assert_eq!(
+            record_log_reader.read_record().await.unwrap(),
+            Some((FileNumber::from(1u32), record))
+        );
+    }
+    assert!(record_log_reader.read_record().await.unwrap().is_none());
+}
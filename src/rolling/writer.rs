@@ -19,25 +19,111 @@
 
 use std::io;
 use std::ops::RangeTo;
+use std::time::{Duration, Instant};
 
-use tokio::fs::File;
 use tokio::io::BufWriter;
 
-const LIMIT_NUM_BYTES: u64 = 50_000_000u64;
-
 use crate::position::FileNumber;
 use crate::record::RecordWriter;
 use crate::rolling::record::Record;
 use crate::rolling::Directory;
+use crate::storage::StorageFile;
+
+/// Defines when the active log file is `fsync`-ed.
+///
+/// Regardless of the policy, a file is always synced before
+/// the log rolls over to a new file. Unless the policy is `Never`, the directory
+/// is synced after files are created or removed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SyncPolicy {
+    /// Sync on every flush. Every acknowledged operation is durable.
+    #[default]
+    OnAppend,
+    /// Sync on flush if the last sync is older than the given delay.
+    ///
+    /// There is no background task: the sync only happens when the log is written to,
+    /// so the last records before a pause may stay unsynced until the next write.
+    OnDelay(Duration),
+    /// Sync on flush once at least the given number of bytes was written
+    /// since the last sync.
+    EveryNBytes(u64),
+    /// Never sync explicitly, and leave it to the OS to write the data to disk.
+    Never,
+}
+
+impl SyncPolicy {
+    fn need_sync(&self, elapsed_since_last_sync: Duration, num_bytes_since_last_sync: u64) -> bool {
+        if num_bytes_since_last_sync == 0 {
+            return false;
+        }
+        match *self {
+            SyncPolicy::OnAppend => true,
+            SyncPolicy::OnDelay(delay) => elapsed_since_last_sync >= delay,
+            SyncPolicy::EveryNBytes(num_bytes) => num_bytes_since_last_sync >= num_bytes,
+            SyncPolicy::Never => false,
+        }
+    }
+
+    // The directory is synced after files are created or removed, unless
+    // syncing is left to the OS.
+    fn need_directory_sync(&self) -> bool {
+        *self != SyncPolicy::Never
+    }
+}
+
+/// Defines when the log rolls over to a new file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RolloverPolicy {
+    /// The log rolls over once the active file reaches this number of bytes.
+    pub num_bytes: u64,
+    /// If set, the log also rolls over once the active file is older than this delay.
+    ///
+    /// As for `SyncPolicy::OnDelay`, there is no background task: the log only rolls
+    /// over when it is written to, and an empty file is never rolled over.
+    pub delay: Option<Duration>,
+}
+
+impl Default for RolloverPolicy {
+    fn default() -> Self {
+        RolloverPolicy {
+            num_bytes: 50_000_000,
+            delay: None,
+        }
+    }
+}
+
+impl RolloverPolicy {
+    fn need_rollover(&self, num_bytes: u64, elapsed_since_creation: Duration) -> bool {
+        if num_bytes >= self.num_bytes {
+            return true;
+        }
+        match self.delay {
+            Some(delay) => num_bytes > 0 && elapsed_since_creation >= delay,
+            None => false,
+        }
+    }
+}
 
 pub struct RecordLogWriter {
-    record_writer_opt: Option<RecordWriter<BufWriter<File>>>,
+    record_writer_opt: Option<RecordWriter<BufWriter<Box<dyn StorageFile>>>>,
     directory: super::Directory,
+    sync_policy: SyncPolicy,
+    rollover_policy: RolloverPolicy,
+    // Creation time of the active file.
+    file_created: Instant,
+    last_sync: Instant,
+    // Number of bytes written in the current file at the time of the last sync.
+    num_bytes_synced: u64,
 }
 
-async fn new_record_writer(directory: &mut Directory) -> io::Result<RecordWriter<BufWriter<File>>> {
-    // TODO sync parent dir.
+async fn new_record_writer(
+    directory: &mut Directory,
+    sync_policy: SyncPolicy,
+) -> io::Result<RecordWriter<BufWriter<Box<dyn StorageFile>>>> {
     let new_file = directory.new_file().await?;
+    if sync_policy.need_directory_sync() {
+        directory.sync().await?;
+    }
     let buf_writer = tokio::io::BufWriter::new(new_file);
     Ok(RecordWriter::open(buf_writer))
 }
@@ -51,8 +137,15 @@ impl RecordLogWriter {
                 .get_mut()
                 .sync_all()
                 .await?;
+            let last_file_number = self.directory.last_file_number();
+            self.directory
+                .set_file_num_bytes(last_file_number, record_writer.num_bytes_written());
         }
-        self.record_writer_opt = Some(new_record_writer(&mut self.directory).await?);
+        self.record_writer_opt =
+            Some(new_record_writer(&mut self.directory, self.sync_policy).await?);
+        self.file_created = Instant::now();
+        self.last_sync = Instant::now();
+        self.num_bytes_synced = 0;
         Ok(())
     }
 
@@ -60,16 +153,73 @@ impl RecordLogWriter {
         self.directory.num_files()
     }
 
-    pub fn open(directory: Directory) -> Self {
+    /// Returns the number of bytes of the log files and of the checkpoint, including
+    /// the records that are written but not flushed yet.
+    pub fn disk_usage(&self) -> u64 {
+        let current_file_num_bytes = self
+            .record_writer_opt
+            .as_ref()
+            .map(|record_writer| record_writer.num_bytes_written())
+            .unwrap_or_default();
+        self.directory.num_bytes() + current_file_num_bytes
+    }
+
+    pub fn open(
+        directory: Directory,
+        sync_policy: SyncPolicy,
+        rollover_policy: RolloverPolicy,
+    ) -> Self {
         RecordLogWriter {
             directory,
             record_writer_opt: None,
+            sync_policy,
+            rollover_policy,
+            file_created: Instant::now(),
+            last_sync: Instant::now(),
+            num_bytes_synced: 0,
         }
     }
 
-    fn need_new_file(&self) -> bool {
+    /// Makes the last file of the directory the active file, holding `num_bytes` bytes.
+    ///
+    /// Records are then appended to this file, until the log rolls over.
+    pub async fn resume_last_file(&mut self, num_bytes: u64) -> io::Result<()> {
+        assert!(self.record_writer_opt.is_none());
+        let last_file_number = self.directory.last_file_number();
+        let file = self
+            .directory
+            .open_file_for_append(last_file_number)
+            .await?;
+        let buf_writer = tokio::io::BufWriter::new(file);
+        self.record_writer_opt = Some(RecordWriter::resume(buf_writer, num_bytes));
+        // The size of the active file is only set once it is complete.
+        self.directory.set_file_num_bytes(last_file_number, 0);
+        self.file_created = Instant::now();
+        self.last_sync = Instant::now();
+        self.num_bytes_synced = num_bytes;
+        Ok(())
+    }
+
+    /// Returns the number of the active file, and the number of bytes written to it.
+    pub fn position(&self) -> Option<(FileNumber, u64)> {
+        let record_writer = self.record_writer_opt.as_ref()?;
+        Some((
+            self.directory.last_file_number(),
+            record_writer.num_bytes_written(),
+        ))
+    }
+
+    pub async fn write_checkpoint(&mut self, checkpoint: &[u8]) -> io::Result<()> {
+        self.directory.write_checkpoint(checkpoint).await
+    }
+
+    /// Returns true if the next record will be written to a new file.
+    pub fn need_new_file(&self) -> bool {
         if let Some(record_writer) = self.record_writer_opt.as_ref() {
-            record_writer.num_bytes_written() >= LIMIT_NUM_BYTES
+            self.rollover_policy.need_rollover(
+                record_writer.num_bytes_written(),
+                self.file_created.elapsed(),
+            )
         } else {
             true
         }
@@ -85,6 +235,12 @@ impl RecordLogWriter {
         Ok(self.directory.last_file_number())
     }
 
+    /// Syncs the active file, and makes the next records be written to a new file.
+    #[cfg(any(test, feature = "simulation"))]
+    pub async fn rollover(&mut self) -> io::Result<()> {
+        self.open_new_file().await
+    }
+
     pub async fn write_record(&mut self, record: Record<'_>) -> io::Result<()> {
         let record_writer = self
             .record_writer_opt
@@ -97,14 +253,112 @@ impl RecordLogWriter {
     /// Remove files that only contain records <= position.
     pub async fn truncate(&mut self, file_to_remove: RangeTo<FileNumber>) -> io::Result<()> {
         self.directory.remove_files(file_to_remove).await?;
+        if self.sync_policy.need_directory_sync() {
+            self.directory.sync().await?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the buffered records, and syncs the file regardless of the sync policy.
+    pub async fn sync(&mut self) -> io::Result<()> {
+        let record_writer = if let Some(record_writer) = self.record_writer_opt.as_mut() {
+            record_writer
+        } else {
+            return Ok(());
+        };
+        record_writer.flush().await?;
+        record_writer
+            .get_underlying_wrt()
+            .get_mut()
+            .sync_data()
+            .await?;
+        self.last_sync = Instant::now();
+        self.num_bytes_synced = record_writer.num_bytes_written();
         Ok(())
     }
 
+    /// Flushes the buffered records, and syncs the file
+    /// if required by the sync policy.
     pub async fn flush(&mut self) -> io::Result<()> {
-        if let Some(record_writer) = self.record_writer_opt.as_mut() {
-            record_writer.flush().await?;
+        let record_writer = if let Some(record_writer) = self.record_writer_opt.as_mut() {
+            record_writer
+        } else {
+            return Ok(());
+        };
+        record_writer.flush().await?;
+        let num_bytes_written = record_writer.num_bytes_written();
+        let need_sync = self.sync_policy.need_sync(
+            self.last_sync.elapsed(),
+            num_bytes_written - self.num_bytes_synced,
+        );
+        if need_sync {
+            record_writer
+                .get_underlying_wrt()
+                .get_mut()
+                .sync_data()
+                .await?;
+            self.last_sync = Instant::now();
+            self.num_bytes_synced = num_bytes_written;
         }
-        // TODO add file-sync according to some sync policy
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{RolloverPolicy, SyncPolicy};
+
+    #[test]
+    fn test_sync_policy_nothing_to_sync() {
+        assert!(!SyncPolicy::OnAppend.need_sync(Duration::from_secs(1), 0));
+        assert!(!SyncPolicy::OnDelay(Duration::ZERO).need_sync(Duration::from_secs(1), 0));
+        assert!(!SyncPolicy::EveryNBytes(0).need_sync(Duration::from_secs(1), 0));
+    }
+
+    #[test]
+    fn test_sync_policy_on_append() {
+        assert!(SyncPolicy::OnAppend.need_sync(Duration::ZERO, 1));
+    }
+
+    #[test]
+    fn test_sync_policy_on_delay() {
+        let sync_policy = SyncPolicy::OnDelay(Duration::from_millis(100));
+        assert!(!sync_policy.need_sync(Duration::from_millis(99), 1_000));
+        assert!(sync_policy.need_sync(Duration::from_millis(100), 1));
+    }
+
+    #[test]
+    fn test_sync_policy_every_n_bytes() {
+        let sync_policy = SyncPolicy::EveryNBytes(1_000);
+        assert!(!sync_policy.need_sync(Duration::from_secs(3_600), 999));
+        assert!(sync_policy.need_sync(Duration::ZERO, 1_000));
+    }
+
+    #[test]
+    fn test_sync_policy_never() {
+        assert!(!SyncPolicy::Never.need_sync(Duration::from_secs(3_600), 1_000_000));
+    }
+
+    #[test]
+    fn test_rollover_policy_num_bytes() {
+        let rollover_policy = RolloverPolicy {
+            num_bytes: 100,
+            delay: None,
+        };
+        assert!(!rollover_policy.need_rollover(99, Duration::from_secs(3_600)));
+        assert!(rollover_policy.need_rollover(100, Duration::ZERO));
+    }
+
+    #[test]
+    fn test_rollover_policy_delay() {
+        let rollover_policy = RolloverPolicy {
+            num_bytes: 100,
+            delay: Some(Duration::from_secs(60)),
+        };
+        assert!(!rollover_policy.need_rollover(1, Duration::from_secs(59)));
+        assert!(rollover_policy.need_rollover(1, Duration::from_secs(60)));
+        assert!(!rollover_policy.need_rollover(0, Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,802 @@
+use std::collections::VecDeque;
+use std::io;
+use std::ops::{Bound, Range, RangeBounds};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use bytes::Bytes;
+use futures_util::stream::{self, Stream};
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::error::{
+    AppendError, CheckpointError, CompactError, CreateQueueError, DeleteQueueError, MissingQueue,
+    RangeError, SubscribeError, TruncateError,
+};
+use crate::mem::{MemQueues, Payload, QueueStats};
+use crate::writer::{MemQueuesAccess, MultiRecordLogWriter};
+use crate::MultiRecordLog;
+
+/// Maximum number of commands processed in a single batch.
+const MAX_BATCH_LEN: usize = 1_024;
+
+const COMMAND_CHANNEL_CAPACITY: usize = 1_024;
+
+/// Maximum number of records fetched at once by a subscription.
+const MAX_SUBSCRIPTION_BATCH_LEN: usize = 1_024;
+
+type Reply<T> = oneshot::Sender<T>;
+
+enum Command {
+    CreateQueue {
+        queue: String,
+        reply: Reply<Result<(), CreateQueueError>>,
+    },
+    DeleteQueue {
+        queue: String,
+        reply: Reply<Result<(), DeleteQueueError>>,
+    },
+    AppendRecord {
+        queue: String,
+        position: Option<u64>,
+        payload: Vec<u8>,
+        reply: Reply<Result<Option<u64>, AppendError>>,
+    },
+    AppendRecords {
+        queue: String,
+        position: Option<u64>,
+        payloads: Vec<Vec<u8>>,
+        reply: Reply<Result<Option<Range<u64>>, AppendError>>,
+    },
+    Truncate {
+        queue: String,
+        position: u64,
+        reply: Reply<Result<(), TruncateError>>,
+    },
+    Compact {
+        reply: Reply<Result<(), CompactError>>,
+    },
+    Checkpoint {
+        reply: Reply<Result<(), CheckpointError>>,
+    },
+}
+
+/// Cloneable handle over a `MultiRecordLog`, that can be used from several tasks concurrently.
+///
+/// Writes are processed in order by a background task, the single writer of the log.
+/// Appends are group-committed: all of the appends that are pending when the task
+/// gets to them are written together and flushed (and synced, depending on the
+/// `SyncPolicy`) once. The future returned by `append_record` only resolves
+/// after the flush of the batch containing the record.
+///
+/// Reads do not go through the task: the in-memory queues are shared behind a lock,
+/// that the writer only takes briefly, once the records are flushed. It is never held
+/// across an `.await`.
+#[derive(Clone)]
+pub struct SharedMultiRecordLog {
+    command_tx: mpsc::Sender<Command>,
+    in_mem_queues: Arc<RwLock<MemQueues>>,
+    // Notified by the writer every time the in-memory queues change.
+    commit_rx: watch::Receiver<()>,
+    // Updated by the writer after each batch of commands.
+    disk_usage: Arc<AtomicU64>,
+}
+
+impl SharedMultiRecordLog {
+    /// Spawns the task owning the `multi_record_log`.
+    ///
+    /// This must be called from within a tokio runtime.
+    pub fn new(multi_record_log: MultiRecordLog) -> Self {
+        let (command_tx, command_rx) = mpsc::channel(COMMAND_CHANNEL_CAPACITY);
+        let (writer, in_mem_queues) = multi_record_log.into_parts();
+        let in_mem_queues = Arc::new(RwLock::new(in_mem_queues));
+        let (commit_tx, commit_rx) = watch::channel(());
+        let disk_usage = Arc::new(AtomicU64::new(writer.disk_usage()));
+        tokio::spawn(run(
+            writer,
+            in_mem_queues.clone(),
+            command_rx,
+            commit_tx,
+            disk_usage.clone(),
+        ));
+        SharedMultiRecordLog {
+            command_tx,
+            in_mem_queues,
+            commit_rx,
+            disk_usage,
+        }
+    }
+
+    // The task only stops once all of the handles are dropped, so a failure to
+    // communicate with it means that it panicked.
+    async fn send_command<T>(&self, command_fn: impl FnOnce(Reply<T>) -> Command) -> T {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.command_tx
+            .send(command_fn(reply_tx))
+            .await
+            .expect("the multi record log task should be running");
+        reply_rx
+            .await
+            .expect("the multi record log task should be running")
+    }
+
+    pub async fn create_queue(&self, queue: &str) -> Result<(), CreateQueueError> {
+        self.send_command(|reply| Command::CreateQueue {
+            queue: queue.to_string(),
+            reply,
+        })
+        .await
+    }
+
+    /// Deletes the queue and all of its records.
+    ///
+    /// See `MultiRecordLog::delete_queue`.
+    pub async fn delete_queue(&self, queue: &str) -> Result<(), DeleteQueueError> {
+        self.send_command(|reply| Command::DeleteQueue {
+            queue: queue.to_string(),
+            reply,
+        })
+        .await
+    }
+
+    /// Appends a record to the log.
+    ///
+    /// See `MultiRecordLog::append_record`.
+    pub async fn append_record(
+        &self,
+        queue: &str,
+        position: Option<u64>,
+        payload: &[u8],
+    ) -> Result<Option<u64>, AppendError> {
+        self.send_command(|reply| Command::AppendRecord {
+            queue: queue.to_string(),
+            position,
+            payload: payload.to_vec(),
+            reply,
+        })
+        .await
+    }
+
+    /// Appends a batch of records to the log.
+    ///
+    /// See `MultiRecordLog::append_records`.
+    pub async fn append_records<'a>(
+        &self,
+        queue: &str,
+        position: Option<u64>,
+        payloads: impl Iterator<Item = &'a [u8]>,
+    ) -> Result<Option<Range<u64>>, AppendError> {
+        let payloads: Vec<Vec<u8>> = payloads.map(|payload| payload.to_vec()).collect();
+        self.send_command(|reply| Command::AppendRecords {
+            queue: queue.to_string(),
+            position,
+            payloads,
+            reply,
+        })
+        .await
+    }
+
+    pub async fn truncate(&self, queue: &str, position: u64) -> Result<(), TruncateError> {
+        self.send_command(|reply| Command::Truncate {
+            queue: queue.to_string(),
+            position,
+            reply,
+        })
+        .await
+    }
+
+    /// Drops this handle, and waits for the log to be closed.
+    ///
+    /// The log is closed once all of the handles are dropped, and the pending
+    /// commands are processed. Its directory can then be opened again.
+    pub async fn close(self) {
+        let SharedMultiRecordLog {
+            command_tx,
+            mut commit_rx,
+            ..
+        } = self;
+        drop(command_tx);
+        // The writer drops its sender when it stops.
+        while commit_rx.changed().await.is_ok() {}
+    }
+
+    /// See `MultiRecordLog::compact`.
+    pub async fn compact(&self) -> Result<(), CompactError> {
+        self.send_command(|reply| Command::Compact { reply }).await
+    }
+
+    /// See `MultiRecordLog::checkpoint`.
+    pub async fn checkpoint(&self) -> Result<(), CheckpointError> {
+        self.send_command(|reply| Command::Checkpoint { reply })
+            .await
+    }
+
+    /// Returns the records of `queue` within the given range of positions.
+    ///
+    /// The payloads are not copied, see `MultiRecordLog::range_bytes`.
+    /// Only the records that were flushed are visible.
+    ///
+    /// The records spilled are read back from the log files once the lock over the
+    /// in-memory queues is released, so that reading them does not block the writer.
+    pub async fn range<R>(&self, queue: &str, range: R) -> Result<Vec<(u64, Bytes)>, RangeError>
+    where
+        R: RangeBounds<u64>,
+    {
+        let range: (Bound<u64>, Bound<u64>) =
+            (range.start_bound().cloned(), range.end_bound().cloned());
+        let (payloads, mut payload_reader) =
+            self.in_mem_queues.with_mem_queues(|in_mem_queues| {
+                let payloads: Vec<(u64, Payload)> =
+                    in_mem_queues.range_payloads(queue, range)?.collect();
+                Ok::<_, MissingQueue>((payloads, in_mem_queues.payload_reader()))
+            })?;
+        let mut records = Vec::with_capacity(payloads.len());
+        for (position, payload) in payloads {
+            let payload = payload.read(position, &mut payload_reader).await?;
+            records.push((position, payload));
+        }
+        Ok(records)
+    }
+
+    /// See `MultiRecordLog::memory_usage`.
+    pub fn memory_usage(&self) -> usize {
+        self.in_mem_queues
+            .with_mem_queues(|in_mem_queues| in_mem_queues.memory_usage())
+    }
+
+    /// Returns the number of bytes of the log files and of the checkpoint on disk,
+    /// as of the last batch of operations processed by the writer.
+    pub fn disk_usage(&self) -> u64 {
+        self.disk_usage.load(Ordering::Relaxed)
+    }
+
+    /// See `MultiRecordLog::queue_stats`.
+    pub fn queue_stats(&self, queue: &str) -> Result<QueueStats, MissingQueue> {
+        self.in_mem_queues
+            .with_mem_queues(|in_mem_queues| in_mem_queues.queue_stats(queue))
+    }
+
+    /// See `MultiRecordLog::position_for_timestamp`.
+    pub fn position_for_timestamp(&self, queue: &str, timestamp: u64) -> Result<u64, MissingQueue> {
+        self.in_mem_queues
+            .with_mem_queues(|in_mem_queues| in_mem_queues.position_for_timestamp(queue, timestamp))
+    }
+
+    /// Tails `queue`, starting at `from_position`.
+    ///
+    /// The stream yields the records of the queue that were already flushed, and then
+    /// waits for new records to be flushed. It yields an error and ends if the queue is
+    /// deleted, even if it is created again in the meantime, or if the next record to
+    /// yield is truncated before it could be read.
+    /// It also ends once all of the handles over the log are dropped.
+    pub fn subscribe(
+        &self,
+        queue: &str,
+        from_position: u64,
+    ) -> Result<impl Stream<Item = Result<(u64, Bytes), SubscribeError>>, MissingQueue> {
+        let incarnation = self
+            .in_mem_queues
+            .with_mem_queues(|in_mem_queues| in_mem_queues.incarnation(queue))?;
+        let subscription = Subscription {
+            queue: queue.to_string(),
+            incarnation,
+            next_position: from_position,
+            in_mem_queues: self.in_mem_queues.clone(),
+            commit_rx: self.commit_rx.clone(),
+            records: VecDeque::new(),
+            error_opt: None,
+            terminated: false,
+        };
+        let subscription_stream = stream::unfold(subscription, |mut subscription| async move {
+            let next_record_res = subscription.next_record().await?;
+            Some((next_record_res, subscription))
+        });
+        Ok(subscription_stream)
+    }
+}
+
+struct Subscription {
+    queue: String,
+    // Incarnation of the queue subscribed to: a queue created again under the same name
+    // is another queue.
+    incarnation: u64,
+    // Position of the next record to fetch from the queue.
+    next_position: u64,
+    in_mem_queues: Arc<RwLock<MemQueues>>,
+    commit_rx: watch::Receiver<()>,
+    // Records fetched but not yielded yet.
+    records: VecDeque<(u64, Bytes)>,
+    // Error ending the subscription, yielded after the records fetched before it.
+    error_opt: Option<SubscribeError>,
+    terminated: bool,
+}
+
+impl Subscription {
+    /// Copies the records of the queue following `next_position`.
+    ///
+    /// The records spilled are read back once the lock over the in-memory queues
+    /// is released.
+    async fn fetch_records(&mut self) -> Result<(), SubscribeError> {
+        let queue = &self.queue;
+        let next_position = self.next_position;
+        let (payloads, mut payload_reader) =
+            self.in_mem_queues.with_mem_queues(|in_mem_queues| {
+                if in_mem_queues.incarnation(queue)? != self.incarnation {
+                    return Err(SubscribeError::MissingQueue(queue.to_string()));
+                }
+                if in_mem_queues.start_position(queue)? > next_position {
+                    return Err(SubscribeError::Truncated(next_position));
+                }
+                let payloads: Vec<(u64, Payload)> = in_mem_queues
+                    .range_payloads(queue, next_position..)?
+                    .take(MAX_SUBSCRIPTION_BATCH_LEN)
+                    .collect();
+                Ok((payloads, in_mem_queues.payload_reader()))
+            })?;
+        for (position, payload) in payloads {
+            let payload = payload.read(position, &mut payload_reader).await?;
+            self.records.push_back((position, payload));
+            self.next_position = position + 1;
+        }
+        Ok(())
+    }
+
+    async fn next_record(&mut self) -> Option<Result<(u64, Bytes), SubscribeError>> {
+        loop {
+            if let Some(record) = self.records.pop_front() {
+                return Some(Ok(record));
+            }
+            if let Some(subscribe_error) = self.error_opt.take() {
+                return Some(Err(subscribe_error));
+            }
+            if self.terminated {
+                return None;
+            }
+            // Marking the commits as seen before reading the queue ensures that
+            // a commit happening in between is not missed.
+            self.commit_rx.borrow_and_update();
+            if let Err(subscribe_error) = self.fetch_records().await {
+                self.terminated = true;
+                self.error_opt = Some(subscribe_error);
+                continue;
+            }
+            // The writer is gone once all of the handles are dropped: no new record
+            // can come.
+            if self.records.is_empty() && self.commit_rx.changed().await.is_err() {
+                self.terminated = true;
+            }
+        }
+    }
+}
+
+enum PendingAppend {
+    Record {
+        position_opt: Option<u64>,
+        reply: Reply<Result<Option<u64>, AppendError>>,
+    },
+    Records {
+        positions_opt: Option<Range<u64>>,
+        reply: Reply<Result<Option<Range<u64>>, AppendError>>,
+    },
+}
+
+// `AppendError` is not `Clone`, because `io::Error` is not.
+fn clone_append_error(append_error: &AppendError) -> AppendError {
+    match append_error {
+        AppendError::IoError(io_error) => {
+            AppendError::IoError(io::Error::new(io_error.kind(), io_error.to_string()))
+        }
+        AppendError::MissingQueue(queue) => AppendError::MissingQueue(queue.clone()),
+        AppendError::Past => AppendError::Past,
+        AppendError::Future => AppendError::Future,
+        AppendError::Poisoned => AppendError::Poisoned,
+        AppendError::Full { limit } => AppendError::Full { limit: *limit },
+    }
+}
+
+impl PendingAppend {
+    fn reply(self, flush_res: &Result<(), AppendError>) {
+        // The caller may have given up on the append. This is fine.
+        match self {
+            PendingAppend::Record {
+                position_opt,
+                reply,
+            } => {
+                let append_res = match flush_res {
+                    Ok(()) => Ok(position_opt),
+                    Err(append_error) => Err(clone_append_error(append_error)),
+                };
+                let _ = reply.send(append_res);
+            }
+            PendingAppend::Records {
+                positions_opt,
+                reply,
+            } => {
+                let append_res = match flush_res {
+                    Ok(()) => Ok(positions_opt),
+                    Err(append_error) => Err(clone_append_error(append_error)),
+                };
+                let _ = reply.send(append_res);
+            }
+        }
+    }
+}
+
+/// Flushes the pending appends, and notifies their callers.
+async fn commit(
+    writer: &mut MultiRecordLogWriter,
+    in_mem_queues: &mut Arc<RwLock<MemQueues>>,
+    pending_appends: &mut Vec<PendingAppend>,
+) {
+    if pending_appends.is_empty() {
+        return;
+    }
+    let flush_res = writer.flush(in_mem_queues).await;
+    for pending_append in pending_appends.drain(..) {
+        pending_append.reply(&flush_res);
+    }
+}
+
+async fn run(
+    mut writer: MultiRecordLogWriter,
+    mut in_mem_queues: Arc<RwLock<MemQueues>>,
+    mut command_rx: mpsc::Receiver<Command>,
+    commit_tx: watch::Sender<()>,
+    disk_usage: Arc<AtomicU64>,
+) {
+    let mut batch: Vec<Command> = Vec::new();
+    let mut pending_appends: Vec<PendingAppend> = Vec::new();
+    while let Some(command) = command_rx.recv().await {
+        batch.push(command);
+        while batch.len() < MAX_BATCH_LEN {
+            if let Ok(command) = command_rx.try_recv() {
+                batch.push(command);
+            } else {
+                break;
+            }
+        }
+        for command in batch.drain(..) {
+            match command {
+                Command::AppendRecord {
+                    queue,
+                    position,
+                    payload,
+                    reply,
+                } => {
+                    match writer
+                        .append_record_without_flush(&in_mem_queues, &queue, position, &payload)
+                        .await
+                    {
+                        Ok(position_opt) => {
+                            pending_appends.push(PendingAppend::Record {
+                                position_opt,
+                                reply,
+                            });
+                        }
+                        Err(append_error) => {
+                            let _ = reply.send(Err(append_error));
+                        }
+                    }
+                }
+                Command::AppendRecords {
+                    queue,
+                    position,
+                    payloads,
+                    reply,
+                } => {
+                    let payloads_it = payloads.iter().map(|payload| payload.as_slice());
+                    match writer
+                        .append_records_without_flush(&in_mem_queues, &queue, position, payloads_it)
+                        .await
+                    {
+                        Ok(positions_opt) => {
+                            pending_appends.push(PendingAppend::Records {
+                                positions_opt,
+                                reply,
+                            });
+                        }
+                        Err(append_error) => {
+                            let _ = reply.send(Err(append_error));
+                        }
+                    }
+                }
+                Command::CreateQueue { queue, reply } => {
+                    commit(&mut writer, &mut in_mem_queues, &mut pending_appends).await;
+                    let _ = reply.send(writer.create_queue(&mut in_mem_queues, &queue).await);
+                }
+                Command::DeleteQueue { queue, reply } => {
+                    commit(&mut writer, &mut in_mem_queues, &mut pending_appends).await;
+                    let _ = reply.send(writer.delete_queue(&mut in_mem_queues, &queue).await);
+                }
+                Command::Compact { reply } => {
+                    commit(&mut writer, &mut in_mem_queues, &mut pending_appends).await;
+                    let _ = reply.send(writer.compact(&mut in_mem_queues).await);
+                }
+                Command::Checkpoint { reply } => {
+                    commit(&mut writer, &mut in_mem_queues, &mut pending_appends).await;
+                    let _ = reply.send(writer.checkpoint(&mut in_mem_queues).await);
+                }
+                Command::Truncate {
+                    queue,
+                    position,
+                    reply,
+                } => {
+                    commit(&mut writer, &mut in_mem_queues, &mut pending_appends).await;
+                    let _ = reply.send(writer.truncate(&mut in_mem_queues, &queue, position).await);
+                }
+            }
+        }
+        commit(&mut writer, &mut in_mem_queues, &mut pending_appends).await;
+        writer.checkpoint_if_needed(&mut in_mem_queues).await;
+        disk_usage.store(writer.disk_usage(), Ordering::Relaxed);
+        // Wakes up the subscriptions.
+        commit_tx.send_replace(());
+    }
+    // Releases the lock of the directory before notifying `close`, by dropping `commit_tx`.
+    drop(writer);
+    drop(commit_tx);
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::join_all;
+    use futures::{StreamExt, TryStreamExt};
+
+    use super::*;
+    use crate::MultiRecordLogOptions;
+
+    #[tokio::test]
+    async fn test_shared_multi_record_log_concurrent_appends() {
+        let tempdir = tempfile::tempdir().unwrap();
+        {
+            let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+            let shared_log = SharedMultiRecordLog::new(multi_record_log);
+            shared_log.create_queue("queue1").await.unwrap();
+            shared_log.create_queue("queue2").await.unwrap();
+            let append_futures = (0..100u64).map(|i| {
+                let shared_log = shared_log.clone();
+                tokio::spawn(async move {
+                    let queue = if i % 2 == 0 { "queue1" } else { "queue2" };
+                    let payload = format!("payload{i}");
+                    shared_log
+                        .append_record(queue, None, payload.as_bytes())
+                        .await
+                })
+            });
+            for append_res in join_all(append_futures).await {
+                assert!(append_res.unwrap().unwrap().is_some());
+            }
+            assert_eq!(shared_log.range("queue1", ..).await.unwrap().len(), 50);
+            assert_eq!(shared_log.range("queue2", ..).await.unwrap().len(), 50);
+            assert_eq!(shared_log.queue_stats("queue1").unwrap().num_records, 50);
+            assert!(shared_log.disk_usage() > 0);
+            shared_log.close().await;
+        }
+        let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        for queue in ["queue1", "queue2"] {
+            let positions: Vec<u64> = multi_record_log
+                .range(queue, ..)
+                .unwrap()
+                .map(|(position, _)| position)
+                .collect();
+            assert_eq!(positions, (0..50).collect::<Vec<u64>>());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_multi_record_log_errors() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        let shared_log = SharedMultiRecordLog::new(multi_record_log);
+        assert!(matches!(
+            shared_log.append_record("queue", None, b"hello").await,
+            Err(AppendError::MissingQueue(_))
+        ));
+        shared_log.create_queue("queue").await.unwrap();
+        assert!(matches!(
+            shared_log.create_queue("queue").await,
+            Err(CreateQueueError::AlreadyExists)
+        ));
+        assert_eq!(
+            shared_log
+                .append_record("queue", Some(0), b"hello")
+                .await
+                .unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            shared_log
+                .append_record("queue", Some(0), b"hello")
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(matches!(
+            shared_log.append_record("queue", Some(3), b"hello").await,
+            Err(AppendError::Future)
+        ));
+        assert_eq!(
+            shared_log
+                .append_records("queue", Some(1), [&b"happy"[..], b"tax"].iter().copied())
+                .await
+                .unwrap(),
+            Some(1..3)
+        );
+        shared_log.truncate("queue", 2).await.unwrap();
+        assert!(shared_log.range("queue", ..).await.unwrap().is_empty());
+        assert!(shared_log.range("missing", ..).await.is_err());
+    }
+
+    #[test]
+    fn test_shared_multi_record_log_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SharedMultiRecordLog>();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_shared_multi_record_log_concurrent_readers() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        let shared_log = SharedMultiRecordLog::new(multi_record_log);
+        shared_log.create_queue("queue").await.unwrap();
+        let reader_handles: Vec<_> = (0..4)
+            .map(|_| {
+                let shared_log = shared_log.clone();
+                tokio::spawn(async move {
+                    let mut num_records = 0;
+                    while num_records < 100 {
+                        let records = shared_log.range("queue", ..).await.unwrap();
+                        // Records become visible in order, without holes.
+                        for (expected_position, (position, payload)) in (0u64..).zip(&records) {
+                            assert_eq!(*position, expected_position);
+                            assert_eq!(&payload[..], format!("payload{position}").as_bytes());
+                        }
+                        assert!(records.len() >= num_records);
+                        num_records = records.len();
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for i in 0..100u64 {
+            let payload = format!("payload{i}");
+            shared_log
+                .append_record("queue", Some(i), payload.as_bytes())
+                .await
+                .unwrap();
+        }
+        for reader_handle in join_all(reader_handles).await {
+            reader_handle.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_multi_record_log_subscribe() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        let shared_log = SharedMultiRecordLog::new(multi_record_log);
+        assert!(shared_log.subscribe("queue", 0).is_err());
+        shared_log.create_queue("queue").await.unwrap();
+        shared_log
+            .append_records("queue", None, [&b"hello"[..], b"happy"].iter().copied())
+            .await
+            .unwrap();
+        let mut subscription = Box::pin(shared_log.subscribe("queue", 1).unwrap());
+        let (position, payload) = subscription.next().await.unwrap().unwrap();
+        assert_eq!(position, 1);
+        assert_eq!(&payload[..], b"happy");
+        let append_handle = {
+            let shared_log = shared_log.clone();
+            tokio::spawn(async move {
+                shared_log
+                    .append_record("queue", None, b"tax")
+                    .await
+                    .unwrap();
+            })
+        };
+        let (position, payload) = subscription.next().await.unwrap().unwrap();
+        assert_eq!(position, 2);
+        assert_eq!(&payload[..], b"tax");
+        append_handle.await.unwrap();
+        let mut lagging_subscription = Box::pin(shared_log.subscribe("queue", 0).unwrap());
+        shared_log.truncate("queue", 1).await.unwrap();
+        assert!(matches!(
+            lagging_subscription.next().await.unwrap(),
+            Err(SubscribeError::Truncated(0))
+        ));
+        assert!(lagging_subscription.next().await.is_none());
+        shared_log.delete_queue("queue").await.unwrap();
+        assert!(matches!(
+            subscription.next().await.unwrap(),
+            Err(SubscribeError::MissingQueue(_))
+        ));
+        assert!(subscription.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shared_multi_record_log_subscribe_queue_recreated() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        let shared_log = SharedMultiRecordLog::new(multi_record_log);
+        shared_log.create_queue("queue").await.unwrap();
+        shared_log
+            .append_record("queue", None, b"hello")
+            .await
+            .unwrap();
+        let mut subscription = Box::pin(shared_log.subscribe("queue", 0).unwrap());
+        let (position, payload) = subscription.next().await.unwrap().unwrap();
+        assert_eq!(position, 0);
+        assert_eq!(&payload[..], b"hello");
+        // Compacting the queue keeps it the same queue.
+        shared_log.compact().await.unwrap();
+        shared_log
+            .append_record("queue", None, b"happy")
+            .await
+            .unwrap();
+        let (position, payload) = subscription.next().await.unwrap().unwrap();
+        assert_eq!(position, 1);
+        assert_eq!(&payload[..], b"happy");
+        // The queue created again has records at the positions the subscription expects,
+        // but it is another queue.
+        shared_log.delete_queue("queue").await.unwrap();
+        shared_log.create_queue("queue").await.unwrap();
+        shared_log
+            .append_records("queue", None, [&b"tax"[..], b"payer", b"!"].iter().copied())
+            .await
+            .unwrap();
+        assert!(matches!(
+            subscription.next().await.unwrap(),
+            Err(SubscribeError::MissingQueue(_))
+        ));
+        assert!(subscription.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shared_multi_record_log_spilled_records() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let options = MultiRecordLogOptions::default().with_spill_num_bytes(5_000);
+        let multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+            .await
+            .unwrap();
+        let shared_log = SharedMultiRecordLog::new(multi_record_log);
+        shared_log.create_queue("queue").await.unwrap();
+        let payloads: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; 1_000]).collect();
+        shared_log
+            .append_records("queue", None, payloads.iter().map(Vec::as_slice))
+            .await
+            .unwrap();
+        let expected_records: Vec<(u64, Bytes)> = (0u64..)
+            .zip(payloads.into_iter().map(Bytes::from))
+            .collect();
+        assert_eq!(
+            shared_log.range("queue", ..).await.unwrap(),
+            expected_records
+        );
+        let subscribed_records: Vec<(u64, Bytes)> = shared_log
+            .subscribe("queue", 0)
+            .unwrap()
+            .take(10)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(subscribed_records, expected_records);
+    }
+
+    #[tokio::test]
+    async fn test_shared_multi_record_log_subscribe_ends_with_log() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        let shared_log = SharedMultiRecordLog::new(multi_record_log);
+        shared_log.create_queue("queue").await.unwrap();
+        let subscription = shared_log.subscribe("queue", 0).unwrap();
+        shared_log
+            .append_record("queue", None, b"hello")
+            .await
+            .unwrap();
+        drop(shared_log);
+        let records: Vec<_> = subscription.collect().await;
+        assert_eq!(records.len(), 1);
+        let (position, payload) = records[0].as_ref().unwrap();
+        assert_eq!(*position, 0);
+        assert_eq!(&payload[..], b"hello");
+    }
+}
@@ -0,0 +1,536 @@
+//! Randomized crash-consistency simulation of a `MultiRecordLog`.
+//!
+//! The simulation runs random operations against a log stored in a `FaultyStorage`, and
+//! against a reference model of the queues: creating, deleting, appending to and truncating
+//! queues, as well as compacting the log, checkpointing it, and rolling it over. It injects IO errors and crashes at random points,
+//! reopens the log, and checks that it agrees with the model: every acknowledged operation
+//! is recovered, nothing is reordered, and each operation that failed is either entirely
+//! recovered or not at all.
+//!
+//! This module requires the `simulation` feature.
+//!
+//! ```
+//! use mrecordlog::simulation::{run_simulation, SimulationConfig};
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let config = SimulationConfig {
+//!     seed: 42,
+//!     num_operations: 100,
+//!     ..Default::default()
+//! };
+//! let report = run_simulation(&config).await.unwrap();
+//! assert_eq!(report.num_operations, 100);
+//! # });
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+
+use futures_util::TryStreamExt;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use thiserror::Error;
+
+use crate::error::{
+    AppendError, CheckpointError, CompactError, CreateQueueError, DeleteQueueError, TruncateError,
+};
+use crate::storage::{Fault, FaultyStorage, MemoryStorage};
+use crate::{MultiRecordLog, MultiRecordLogOptions, SyncPolicy};
+
+/// Parameters of a simulation.
+#[derive(Clone, Copy, Debug)]
+pub struct SimulationConfig {
+    /// Seed of the random operations: a simulation is reproducible from its seed.
+    pub seed: u64,
+    pub num_operations: usize,
+    /// Number of queues the operations are spread over.
+    pub num_queues: usize,
+    /// Maximum number of bytes of the payloads appended.
+    pub max_payload_num_bytes: usize,
+    /// Probability of injecting an IO error before each operation.
+    pub fault_probability: f64,
+    /// Probability of crashing after each operation.
+    pub crash_probability: f64,
+    /// Options the log is opened with.
+    ///
+    /// The sync policy is always `SyncPolicy::OnAppend`, so that every acknowledged
+    /// operation is durable.
+    pub options: MultiRecordLogOptions,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            seed: 0,
+            num_operations: 1_000,
+            num_queues: 3,
+            max_payload_num_bytes: 40_000,
+            fault_probability: 0.05,
+            crash_probability: 0.02,
+            options: MultiRecordLogOptions::default().with_rollover_num_bytes(100_000),
+        }
+    }
+}
+
+/// Statistics of a simulation that ran successfully.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SimulationReport {
+    pub num_operations: usize,
+    /// Number of operations that failed because of an IO error injected.
+    pub num_failed_operations: usize,
+    /// Number of times the log was reopened, after a crash or not.
+    pub num_reopenings: usize,
+    pub num_crashes: usize,
+}
+
+/// Disagreement between the log and the model.
+#[derive(Debug, Error)]
+#[error("simulation with seed {seed} failed after {num_operations} operations: {reason}")]
+pub struct SimulationError {
+    pub seed: u64,
+    pub num_operations: usize,
+    pub reason: String,
+}
+
+#[derive(Default)]
+struct QueueModel {
+    // Position of the first record retained.
+    start_position: u64,
+    // Records acknowledged, and not truncated.
+    records: VecDeque<Vec<u8>>,
+    // Record of the last append, if it failed.
+    failed_append_opt: Option<Vec<u8>>,
+    // Position of the last truncation, if it failed.
+    failed_truncation_opt: Option<u64>,
+}
+
+impl QueueModel {
+    fn next_position(&self) -> u64 {
+        self.start_position + self.records.len() as u64
+    }
+
+    fn truncate(&mut self, position: u64) {
+        while self.start_position <= position && !self.records.is_empty() {
+            self.records.pop_front();
+            self.start_position += 1;
+        }
+    }
+
+    /// Checks the records recovered after reopening the log, and resolves the outcome of
+    /// the operations that failed.
+    fn check_recovered(&mut self, records: &[(u64, Vec<u8>)]) -> Result<(), String> {
+        let next_position = self.next_position();
+        let failed_truncation_opt = self.failed_truncation_opt.take();
+        let failed_append_opt = self.failed_append_opt.take();
+        let (first_position, last_position) = match (records.first(), records.last()) {
+            (Some((first_position, _)), Some((last_position, _))) => {
+                (*first_position, *last_position)
+            }
+            _ => {
+                let all_truncated = self.records.is_empty()
+                    || failed_truncation_opt.map(|position| position + 1) >= Some(next_position);
+                if !all_truncated {
+                    return Err(format!(
+                        "records {}..{next_position} were lost",
+                        self.start_position
+                    ));
+                }
+                if let Some(position) = failed_truncation_opt {
+                    self.truncate(position);
+                }
+                return Ok(());
+            }
+        };
+        if first_position != self.start_position
+            && failed_truncation_opt.map(|position| position + 1) != Some(first_position)
+        {
+            return Err(format!(
+                "the first record is at position {first_position}, instead of {}",
+                self.start_position
+            ));
+        }
+        let expected_last_position = if failed_append_opt.is_some() {
+            next_position
+        } else {
+            next_position - 1
+        };
+        if last_position + 1 < next_position || last_position > expected_last_position {
+            return Err(format!(
+                "the last record is at position {last_position}, while the next position is \
+                 {next_position}"
+            ));
+        }
+        for (i, (position, payload)) in records.iter().enumerate() {
+            if *position != first_position + i as u64 {
+                return Err(format!("the record at position {position} is out of order"));
+            }
+            let expected_payload_opt = if *position < next_position {
+                self.records
+                    .get((*position - self.start_position) as usize)
+                    .map(Vec::as_slice)
+            } else {
+                failed_append_opt.as_deref()
+            };
+            if expected_payload_opt != Some(payload.as_slice()) {
+                return Err(format!(
+                    "the record at position {position} has the wrong payload"
+                ));
+            }
+        }
+        if first_position > self.start_position {
+            self.truncate(first_position - 1);
+        }
+        if last_position == next_position {
+            self.records.extend(failed_append_opt);
+        }
+        Ok(())
+    }
+}
+
+struct Simulation {
+    config: SimulationConfig,
+    options: MultiRecordLogOptions,
+    rng: StdRng,
+    storage: FaultyStorage,
+    log: MultiRecordLog,
+    queues: BTreeMap<String, QueueModel>,
+    // Queue of the last creation, if it failed.
+    failed_creation_opt: Option<String>,
+    // Queue of the last deletion, if it failed.
+    failed_deletion_opt: Option<String>,
+    report: SimulationReport,
+}
+
+impl Simulation {
+    async fn open(config: &SimulationConfig) -> Result<Simulation, String> {
+        let options = config.options.with_sync_policy(SyncPolicy::OnAppend);
+        let storage = FaultyStorage::new(MemoryStorage::default());
+        let log = open_log(&storage, options).await?;
+        Ok(Simulation {
+            config: *config,
+            options,
+            rng: StdRng::seed_from_u64(config.seed),
+            storage,
+            log,
+            queues: BTreeMap::new(),
+            failed_creation_opt: None,
+            failed_deletion_opt: None,
+            report: SimulationReport::default(),
+        })
+    }
+
+    fn random_payload(&mut self) -> Vec<u8> {
+        let max_num_bytes = if self.rng.gen_bool(0.5) {
+            self.config.max_payload_num_bytes.min(100)
+        } else {
+            self.config.max_payload_num_bytes
+        };
+        let mut payload = vec![0u8; self.rng.gen_range(0..=max_num_bytes)];
+        self.rng.fill(&mut payload[..]);
+        payload
+    }
+
+    fn maybe_inject_fault(&mut self) {
+        if !self.rng.gen_bool(self.config.fault_probability) {
+            return;
+        }
+        let fault = match self.rng.gen_range(0..3) {
+            0 => Fault::IoError,
+            1 => Fault::NoSpace,
+            _ => Fault::ShortWrite,
+        };
+        let num_bytes = self
+            .rng
+            .gen_range(0..=2 * self.config.max_payload_num_bytes as u64);
+        self.storage.inject_fault(fault, num_bytes);
+    }
+
+    /// Runs a random operation, and returns false if it failed.
+    async fn run_operation(&mut self) -> Result<bool, String> {
+        match self.rng.gen_range(0..100) {
+            0..=1 => return self.compact().await,
+            2..=3 => return self.checkpoint().await,
+            4..=5 => return self.rollover().await,
+            _ => {}
+        }
+        let queue = format!("queue-{}", self.rng.gen_range(0..self.config.num_queues));
+        let next_position_opt = self.queues.get(&queue).map(QueueModel::next_position);
+        match next_position_opt {
+            None => self.create_queue(queue).await,
+            Some(_) if self.rng.gen_bool(0.03) => self.delete_queue(queue).await,
+            Some(next_position) if next_position > 0 && self.rng.gen_bool(0.15) => {
+                let position = self.rng.gen_range(0..next_position);
+                self.truncate(queue, position).await
+            }
+            Some(next_position) => self.append_record(queue, next_position).await,
+        }
+    }
+
+    async fn create_queue(&mut self, queue: String) -> Result<bool, String> {
+        match self.log.create_queue(&queue).await {
+            Ok(()) => {
+                self.queues.insert(queue.clone(), QueueModel::default());
+                self.check_queue(&queue).await?;
+                Ok(true)
+            }
+            Err(CreateQueueError::IoError(_)) => {
+                self.failed_creation_opt = Some(queue);
+                Ok(false)
+            }
+            Err(create_queue_error) => Err(format!(
+                "failed to create queue `{queue}`: {create_queue_error}"
+            )),
+        }
+    }
+
+    async fn delete_queue(&mut self, queue: String) -> Result<bool, String> {
+        match self.log.delete_queue(&queue).await {
+            Ok(()) => {
+                self.queues.remove(&queue);
+                if self.log.range(&queue, ..).is_ok() {
+                    return Err(format!("the queue `{queue}` is still there once deleted"));
+                }
+                Ok(true)
+            }
+            Err(DeleteQueueError::IoError(_)) => {
+                self.failed_deletion_opt = Some(queue);
+                Ok(false)
+            }
+            Err(delete_queue_error) => Err(format!(
+                "failed to delete queue `{queue}`: {delete_queue_error}"
+            )),
+        }
+    }
+
+    async fn append_record(&mut self, queue: String, next_position: u64) -> Result<bool, String> {
+        let payload = self.random_payload();
+        match self.log.append_record(&queue, None, &payload).await {
+            Ok(position_opt) => {
+                if position_opt != Some(next_position) {
+                    return Err(format!(
+                        "the record appended to `{queue}` got position {position_opt:?}, instead \
+                         of {next_position}"
+                    ));
+                }
+                self.queues
+                    .get_mut(&queue)
+                    .unwrap()
+                    .records
+                    .push_back(payload);
+                self.check_queue(&queue).await?;
+                Ok(true)
+            }
+            Err(AppendError::IoError(_)) => {
+                self.queues.get_mut(&queue).unwrap().failed_append_opt = Some(payload);
+                Ok(false)
+            }
+            Err(append_error) => Err(format!(
+                "failed to append a record to `{queue}`: {append_error}"
+            )),
+        }
+    }
+
+    async fn truncate(&mut self, queue: String, position: u64) -> Result<bool, String> {
+        match self.log.truncate(&queue, position).await {
+            Ok(()) => {
+                self.queues.get_mut(&queue).unwrap().truncate(position);
+                self.check_queue(&queue).await?;
+                Ok(true)
+            }
+            Err(TruncateError::IoError(_)) => {
+                self.queues.get_mut(&queue).unwrap().failed_truncation_opt = Some(position);
+                Ok(false)
+            }
+            Err(truncate_error) => Err(format!(
+                "failed to truncate `{queue}` up to {position}: {truncate_error}"
+            )),
+        }
+    }
+
+    async fn compact(&mut self) -> Result<bool, String> {
+        match self.log.compact().await {
+            Ok(()) => {
+                self.check_queues().await?;
+                Ok(true)
+            }
+            Err(CompactError::IoError(_)) => Ok(false),
+            Err(compact_error) => Err(format!("failed to compact the log: {compact_error}")),
+        }
+    }
+
+    async fn checkpoint(&mut self) -> Result<bool, String> {
+        match self.log.checkpoint().await {
+            Ok(()) => {
+                self.check_queues().await?;
+                Ok(true)
+            }
+            Err(CheckpointError::IoError(_)) => Ok(false),
+            Err(checkpoint_error) => {
+                Err(format!("failed to checkpoint the log: {checkpoint_error}"))
+            }
+        }
+    }
+
+    async fn rollover(&mut self) -> Result<bool, String> {
+        match self.log.rollover().await {
+            Ok(()) => {
+                self.check_queues().await?;
+                Ok(true)
+            }
+            Err(AppendError::IoError(_)) => Ok(false),
+            Err(rollover_error) => Err(format!("failed to roll the log over: {rollover_error}")),
+        }
+    }
+
+    async fn check_queues(&self) -> Result<(), String> {
+        for queue in self.queues.keys() {
+            self.check_queue(queue).await?;
+        }
+        Ok(())
+    }
+
+    // Checks that the records of the queue held by the log are the ones of the model.
+    async fn check_queue(&self, queue: &str) -> Result<(), String> {
+        let queue_model = &self.queues[queue];
+        let records = self.records(queue).await?;
+        let expected_records: Vec<(u64, Vec<u8>)> = (queue_model.start_position..)
+            .zip(queue_model.records.iter().cloned())
+            .collect();
+        if records != expected_records {
+            return Err(format!("the records of `{queue}` do not match the model"));
+        }
+        Ok(())
+    }
+
+    async fn records(&self, queue: &str) -> Result<Vec<(u64, Vec<u8>)>, String> {
+        self.log
+            .range_stream(queue, ..)
+            .map_err(|_| format!("the queue `{queue}` is missing"))?
+            .map_ok(|(position, payload)| (position, payload.into_owned()))
+            .try_collect()
+            .await
+            .map_err(|read_error| format!("failed to read `{queue}`: {read_error}"))
+    }
+
+    /// Reopens the log, after a crash or not, and checks the queues recovered.
+    async fn reopen(&mut self, crash: bool) -> Result<(), String> {
+        if crash {
+            let num_unsynced_bytes_kept = match self.rng.gen_range(0..3) {
+                0 => 0,
+                1 => self
+                    .rng
+                    .gen_range(0..=self.config.max_payload_num_bytes as u64),
+                _ => u64::MAX,
+            };
+            self.storage
+                .crash(num_unsynced_bytes_kept)
+                .await
+                .map_err(|io_error| format!("failed to crash: {io_error}"))?;
+            self.report.num_crashes += 1;
+        } else {
+            self.storage.heal();
+        }
+        self.log = open_log(&self.storage, self.options).await?;
+        self.report.num_reopenings += 1;
+        if let Some(queue) = self.failed_creation_opt.take() {
+            if self.log.range(&queue, ..).is_ok() {
+                self.queues.insert(queue, QueueModel::default());
+            }
+        }
+        if let Some(queue) = self.failed_deletion_opt.take() {
+            if self.log.range(&queue, ..).is_err() {
+                self.queues.remove(&queue);
+            }
+        }
+        for queue in self.queues.keys().cloned().collect::<Vec<String>>() {
+            let records = self.records(&queue).await?;
+            self.queues
+                .get_mut(&queue)
+                .unwrap()
+                .check_recovered(&records)
+                .map_err(|reason| format!("queue `{queue}`: {reason}"))?;
+        }
+        Ok(())
+    }
+
+    async fn run(&mut self) -> Result<(), String> {
+        while self.report.num_operations < self.config.num_operations {
+            self.maybe_inject_fault();
+            let succeeded = self.run_operation().await?;
+            self.report.num_operations += 1;
+            if !succeeded {
+                // The log may be poisoned, and needs to be reopened.
+                self.report.num_failed_operations += 1;
+                let crash = self.rng.gen_bool(0.5);
+                self.reopen(crash).await?;
+            } else if self.rng.gen_bool(self.config.crash_probability) {
+                self.reopen(true).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn open_log(
+    storage: &FaultyStorage,
+    options: MultiRecordLogOptions,
+) -> Result<MultiRecordLog, String> {
+    MultiRecordLog::open_with_storage(Arc::new(storage.clone()), options)
+        .await
+        .map_err(|read_record_error| format!("failed to open the log: {read_record_error}"))
+}
+
+/// Runs a simulation, and returns an error describing the first disagreement between
+/// the log and the model.
+pub async fn run_simulation(
+    config: &SimulationConfig,
+) -> Result<SimulationReport, SimulationError> {
+    let simulation_error = |num_operations: usize, reason: String| SimulationError {
+        seed: config.seed,
+        num_operations,
+        reason,
+    };
+    let mut simulation = Simulation::open(config)
+        .await
+        .map_err(|reason| simulation_error(0, reason))?;
+    if let Err(reason) = simulation.run().await {
+        return Err(simulation_error(simulation.report.num_operations, reason));
+    }
+    Ok(simulation.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_simulation() {
+        for seed in 0..20 {
+            let config = SimulationConfig {
+                seed,
+                num_operations: 300,
+                fault_probability: 0.1,
+                ..Default::default()
+            };
+            let report = run_simulation(&config).await.unwrap();
+            assert_eq!(report.num_operations, 300);
+            assert!(report.num_failed_operations > 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulation_with_checkpoints_and_spill() {
+        let config = SimulationConfig {
+            num_operations: 300,
+            options: MultiRecordLogOptions::default()
+                .with_rollover_num_bytes(50_000)
+                .with_checkpoint_interval(Duration::ZERO)
+                .with_spill_num_bytes(100_000)
+                .with_resume_last_file(true),
+            ..Default::default()
+        };
+        run_simulation(&config).await.unwrap();
+    }
+}
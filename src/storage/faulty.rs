@@ -0,0 +1,499 @@
+// Copyright (C) 2022 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::storage::{Storage, StorageFile};
+
+/// Fault injected by a `FaultyStorage`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Fault {
+    /// Writes fail with an `io::ErrorKind::StorageFull` error, as with `ENOSPC`.
+    NoSpace,
+    /// Writes and syncs fail with an IO error, as with `EIO`.
+    IoError,
+    /// A single write only writes part of its buffer, and reports it.
+    ShortWrite,
+}
+
+impl Fault {
+    fn io_error(&self) -> io::Error {
+        match self {
+            Fault::NoSpace => io::Error::new(
+                io::ErrorKind::StorageFull,
+                "no space left on device (injected)",
+            ),
+            Fault::IoError | Fault::ShortWrite => io::Error::other("input/output error (injected)"),
+        }
+    }
+}
+
+fn crashed_error() -> io::Error {
+    io::Error::other("the file was opened before a crash (injected)")
+}
+
+#[derive(Default)]
+struct FileState {
+    // Number of bytes of the file, as written.
+    len: u64,
+    // Number of bytes of the file that survive a crash.
+    synced_len: u64,
+}
+
+#[derive(Default)]
+struct State {
+    files: HashMap<String, Arc<Mutex<FileState>>>,
+    // Files dropped by a crash, as their creation was not synced yet.
+    created_since_sync: HashSet<String>,
+    // Fault injected, and the number of bytes left to write before it is triggered.
+    pending_fault_opt: Option<(Fault, u64)>,
+    // Fault triggered, failing all the writes until the storage is healed.
+    fault_opt: Option<Fault>,
+    // Incremented by each crash, to fail the files opened before it.
+    generation: u64,
+}
+
+/// Storage wrapper injecting IO errors and crashes, to test how the log recovers from them.
+///
+/// The files are expected to be appended to, as the log does. A crash drops the bytes
+/// written to each file since it was last synced, and the files created since the storage
+/// was last synced. The removal and the renaming of files are considered durable right away.
+///
+/// The clones of a storage share its state, so that faults can be injected while a log uses it.
+#[derive(Clone)]
+pub struct FaultyStorage {
+    inner: Arc<dyn Storage>,
+    state: Arc<Mutex<State>>,
+}
+
+impl FaultyStorage {
+    pub fn new(inner: impl Storage) -> Self {
+        FaultyStorage {
+            inner: Arc::new(inner),
+            state: Arc::default(),
+        }
+    }
+
+    /// Injects `fault` once `num_bytes` more bytes are written.
+    ///
+    /// The write reaching this point only writes the bytes before it, and the next write
+    /// triggers the fault. `Fault::NoSpace` and `Fault::IoError` thus leave a torn write
+    /// behind, and keep failing until the storage is healed.
+    pub fn inject_fault(&self, fault: Fault, num_bytes: u64) {
+        self.state.lock().unwrap().pending_fault_opt = Some((fault, num_bytes));
+    }
+
+    /// Removes the faults injected or triggered.
+    pub fn heal(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending_fault_opt = None;
+        state.fault_opt = None;
+    }
+
+    /// Simulates a crash, followed by a restart.
+    ///
+    /// Each file keeps at most `num_unsynced_bytes_kept` of the bytes written since it was
+    /// last synced: 0 drops everything not synced, and other values leave torn writes behind.
+    /// The files opened before the crash fail all operations, and the storage is healed.
+    pub async fn crash(&self, num_unsynced_bytes_kept: u64) -> io::Result<()> {
+        let (created_since_sync, files) = {
+            let mut state = self.state.lock().unwrap();
+            state.generation += 1;
+            state.pending_fault_opt = None;
+            state.fault_opt = None;
+            let created_since_sync = std::mem::take(&mut state.created_since_sync);
+            for name in &created_since_sync {
+                state.files.remove(name);
+            }
+            let files: Vec<(String, Arc<Mutex<FileState>>)> = state
+                .files
+                .iter()
+                .map(|(name, file_state)| (name.clone(), file_state.clone()))
+                .collect();
+            (created_since_sync, files)
+        };
+        for name in created_since_sync {
+            match self.inner.remove(&name).await {
+                Err(io_error) if io_error.kind() != io::ErrorKind::NotFound => {
+                    return Err(io_error)
+                }
+                _ => {}
+            }
+        }
+        for (name, file_state) in files {
+            let kept_len = {
+                let mut file_state = file_state.lock().unwrap();
+                let kept_len = file_state.len.min(
+                    file_state
+                        .synced_len
+                        .saturating_add(num_unsynced_bytes_kept),
+                );
+                let is_truncated = kept_len < file_state.len;
+                file_state.len = kept_len;
+                file_state.synced_len = kept_len;
+                if !is_truncated {
+                    continue;
+                }
+                kept_len
+            };
+            let mut file = self.inner.open(&name).await?;
+            file.set_len(kept_len).await?;
+            file.sync_all().await?;
+        }
+        Ok(())
+    }
+
+    fn generation(&self) -> u64 {
+        self.state.lock().unwrap().generation
+    }
+
+    fn check_fault(&self) -> io::Result<()> {
+        if self.state.lock().unwrap().fault_opt == Some(Fault::IoError) {
+            return Err(Fault::IoError.io_error());
+        }
+        Ok(())
+    }
+
+    async fn file_state(&self, name: &str) -> io::Result<Arc<Mutex<FileState>>> {
+        if let Some(file_state) = self.state.lock().unwrap().files.get(name) {
+            return Ok(file_state.clone());
+        }
+        // The files written before the storage was wrapped are considered synced.
+        let len = self
+            .inner
+            .list()
+            .await?
+            .into_iter()
+            .find(|(file_name, _)| file_name == name)
+            .map(|(_, len)| len)
+            .unwrap_or_default();
+        let file_state = self
+            .state
+            .lock()
+            .unwrap()
+            .files
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                Arc::new(Mutex::new(FileState {
+                    len,
+                    synced_len: len,
+                }))
+            })
+            .clone();
+        Ok(file_state)
+    }
+
+    fn wrap_file(
+        &self,
+        inner: Box<dyn StorageFile>,
+        file_state: Arc<Mutex<FileState>>,
+    ) -> Box<dyn StorageFile> {
+        Box::new(FaultyFile {
+            inner,
+            storage: self.clone(),
+            file_state,
+            generation: self.generation(),
+            position: 0,
+        })
+    }
+}
+
+/// File of a `FaultyStorage`.
+struct FaultyFile {
+    inner: Box<dyn StorageFile>,
+    storage: FaultyStorage,
+    file_state: Arc<Mutex<FileState>>,
+    generation: u64,
+    position: u64,
+}
+
+impl FaultyFile {
+    fn check_not_crashed(&self) -> io::Result<()> {
+        if self.storage.generation() != self.generation {
+            return Err(crashed_error());
+        }
+        Ok(())
+    }
+}
+
+impl AsyncRead for FaultyFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let file = self.get_mut();
+        file.check_not_crashed()?;
+        let num_bytes_before = buf.filled().len();
+        let poll_res = Pin::new(&mut file.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll_res {
+            file.position += (buf.filled().len() - num_bytes_before) as u64;
+        }
+        poll_res
+    }
+}
+
+impl AsyncWrite for FaultyFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let file = self.get_mut();
+        file.check_not_crashed()?;
+        let mut write_len = buf.len();
+        {
+            let mut state = file.storage.state.lock().unwrap();
+            if let Some(fault) = state.fault_opt {
+                return Poll::Ready(Err(fault.io_error()));
+            }
+            match state.pending_fault_opt {
+                Some((fault, 0)) => {
+                    state.pending_fault_opt = None;
+                    if fault != Fault::ShortWrite {
+                        state.fault_opt = Some(fault);
+                        return Poll::Ready(Err(fault.io_error()));
+                    }
+                }
+                Some((_, num_bytes_before_fault)) => {
+                    write_len = write_len.min(num_bytes_before_fault as usize);
+                }
+                None => {}
+            }
+        }
+        let poll_res = Pin::new(&mut file.inner).poll_write(cx, &buf[..write_len]);
+        if let Poll::Ready(Ok(num_bytes)) = poll_res {
+            if let Some((_, num_bytes_before_fault)) = file
+                .storage
+                .state
+                .lock()
+                .unwrap()
+                .pending_fault_opt
+                .as_mut()
+            {
+                *num_bytes_before_fault -= num_bytes as u64;
+            }
+            file.position += num_bytes as u64;
+            let mut file_state = file.file_state.lock().unwrap();
+            file_state.len = file_state.len.max(file.position);
+        }
+        poll_res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let file = self.get_mut();
+        file.check_not_crashed()?;
+        Pin::new(&mut file.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let file = self.get_mut();
+        file.check_not_crashed()?;
+        Pin::new(&mut file.inner).poll_shutdown(cx)
+    }
+}
+
+impl AsyncSeek for FaultyFile {
+    fn start_seek(self: Pin<&mut Self>, seek_from: SeekFrom) -> io::Result<()> {
+        let file = self.get_mut();
+        file.check_not_crashed()?;
+        Pin::new(&mut file.inner).start_seek(seek_from)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let file = self.get_mut();
+        let poll_res = Pin::new(&mut file.inner).poll_complete(cx);
+        if let Poll::Ready(Ok(position)) = poll_res {
+            file.position = position;
+        }
+        poll_res
+    }
+}
+
+#[async_trait]
+impl StorageFile for FaultyFile {
+    async fn sync_data(&mut self) -> io::Result<()> {
+        self.sync_all().await
+    }
+
+    async fn sync_all(&mut self) -> io::Result<()> {
+        self.check_not_crashed()?;
+        self.storage.check_fault()?;
+        self.inner.sync_all().await?;
+        let mut file_state = self.file_state.lock().unwrap();
+        file_state.synced_len = file_state.len;
+        Ok(())
+    }
+
+    async fn set_len(&mut self, num_bytes: u64) -> io::Result<()> {
+        self.check_not_crashed()?;
+        self.inner.set_len(num_bytes).await?;
+        let mut file_state = self.file_state.lock().unwrap();
+        file_state.len = num_bytes;
+        file_state.synced_len = file_state.synced_len.min(num_bytes);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for FaultyStorage {
+    async fn list(&self) -> io::Result<Vec<(String, u64)>> {
+        self.inner.list().await
+    }
+
+    async fn create(&self, name: &str) -> io::Result<Box<dyn StorageFile>> {
+        let file = self.inner.create(name).await?;
+        let file_state: Arc<Mutex<FileState>> = Arc::default();
+        let mut state = self.state.lock().unwrap();
+        state.files.insert(name.to_string(), file_state.clone());
+        state.created_since_sync.insert(name.to_string());
+        drop(state);
+        Ok(self.wrap_file(file, file_state))
+    }
+
+    async fn open(&self, name: &str) -> io::Result<Box<dyn StorageFile>> {
+        let file = self.inner.open(name).await?;
+        let file_state = self.file_state(name).await?;
+        Ok(self.wrap_file(file, file_state))
+    }
+
+    async fn remove(&self, name: &str) -> io::Result<()> {
+        self.inner.remove(name).await?;
+        let mut state = self.state.lock().unwrap();
+        state.files.remove(name);
+        state.created_since_sync.remove(name);
+        Ok(())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        self.inner.rename(from, to).await?;
+        let mut state = self.state.lock().unwrap();
+        match state.files.remove(from) {
+            Some(file_state) => {
+                state.files.insert(to.to_string(), file_state);
+            }
+            None => {
+                state.files.remove(to);
+            }
+        }
+        if state.created_since_sync.remove(from) {
+            state.created_since_sync.insert(to.to_string());
+        } else {
+            state.created_since_sync.remove(to);
+        }
+        Ok(())
+    }
+
+    async fn sync(&self) -> io::Result<()> {
+        self.check_fault()?;
+        self.inner.sync().await?;
+        self.state.lock().unwrap().created_since_sync.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    async fn file_content(storage: &MemoryStorage, name: &str) -> Vec<u8> {
+        let mut file = storage.open(name).await.unwrap();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).await.unwrap();
+        content
+    }
+
+    #[tokio::test]
+    async fn test_faulty_storage_crash() {
+        let memory_storage = MemoryStorage::default();
+        let storage = FaultyStorage::new(memory_storage.clone());
+        let mut file = storage.create("synced").await.unwrap();
+        file.write_all(b"hello").await.unwrap();
+        file.sync_all().await.unwrap();
+        storage.sync().await.unwrap();
+        file.write_all(b" world").await.unwrap();
+        let mut unsynced_file = storage.create("unsynced").await.unwrap();
+        unsynced_file.sync_all().await.unwrap();
+        storage.crash(0).await.unwrap();
+        assert_eq!(
+            memory_storage.list().await.unwrap(),
+            vec![("synced".to_string(), 5)]
+        );
+        let write_error = file.write_all(b"!").await.err().unwrap();
+        assert_eq!(write_error.to_string(), crashed_error().to_string());
+        let mut file = storage.open("synced").await.unwrap();
+        file.seek(SeekFrom::End(0)).await.unwrap();
+        file.write_all(b" world").await.unwrap();
+        // A torn write keeps some of the bytes not synced.
+        storage.crash(3).await.unwrap();
+        assert_eq!(file_content(&memory_storage, "synced").await, b"hello wo");
+    }
+
+    #[tokio::test]
+    async fn test_faulty_storage_no_space() {
+        let memory_storage = MemoryStorage::default();
+        let storage = FaultyStorage::new(memory_storage.clone());
+        let mut file = storage.create("file").await.unwrap();
+        storage.inject_fault(Fault::NoSpace, 3);
+        let write_error = file.write_all(b"hello").await.err().unwrap();
+        assert_eq!(write_error.kind(), io::ErrorKind::StorageFull);
+        assert_eq!(file_content(&memory_storage, "file").await, b"hel");
+        let write_error = file.write_all(b"lo").await.err().unwrap();
+        assert_eq!(write_error.kind(), io::ErrorKind::StorageFull);
+        // Syncs still succeed.
+        file.sync_all().await.unwrap();
+        storage.heal();
+        file.write_all(b"lo").await.unwrap();
+        assert_eq!(file_content(&memory_storage, "file").await, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_faulty_storage_io_error() {
+        let storage = FaultyStorage::new(MemoryStorage::default());
+        let mut file = storage.create("file").await.unwrap();
+        storage.inject_fault(Fault::IoError, 0);
+        assert!(file.write_all(b"hello").await.is_err());
+        assert!(file.sync_all().await.is_err());
+        assert!(storage.sync().await.is_err());
+        storage.heal();
+        file.sync_all().await.unwrap();
+        storage.sync().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_faulty_storage_short_write() {
+        let memory_storage = MemoryStorage::default();
+        let storage = FaultyStorage::new(memory_storage.clone());
+        let mut file = storage.create("file").await.unwrap();
+        storage.inject_fault(Fault::ShortWrite, 2);
+        assert_eq!(file.write(b"hello").await.unwrap(), 2);
+        assert_eq!(file.write(b"llo").await.unwrap(), 3);
+        assert_eq!(file_content(&memory_storage, "file").await, b"hello");
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright (C) 2022 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use fs4::fs_std::FileExt;
+use tokio::fs::{File, OpenOptions};
+
+use crate::storage::{Storage, StorageFile};
+
+const LOCK_FILENAME: &str = "lock";
+
+/// Storage of the files in a directory of the local filesystem.
+///
+/// The directory is locked as long as the storage is alive.
+pub struct LocalStorage {
+    dir_path: PathBuf,
+    // Holds an exclusive lock on the directory, released when dropped.
+    _lock_file: std::fs::File,
+}
+
+async fn lock(dir_path: &Path) -> io::Result<std::fs::File> {
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir_path.join(LOCK_FILENAME))
+        .await?
+        .into_std()
+        .await;
+    if !lock_file.try_lock_exclusive()? {
+        return Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            format!(
+                "the directory `{}` is locked: the log is already opened",
+                dir_path.display()
+            ),
+        ));
+    }
+    Ok(lock_file)
+}
+
+impl LocalStorage {
+    /// Opens the directory, and locks it.
+    ///
+    /// Fails with an `io::ErrorKind::WouldBlock` error if the directory is already
+    /// locked, by this process or by another one.
+    pub async fn open(dir_path: &Path) -> io::Result<LocalStorage> {
+        let lock_file = lock(dir_path).await?;
+        Ok(LocalStorage {
+            dir_path: dir_path.to_path_buf(),
+            _lock_file: lock_file,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir_path
+    }
+}
+
+#[async_trait]
+impl StorageFile for File {
+    async fn sync_data(&mut self) -> io::Result<()> {
+        File::sync_data(self).await
+    }
+
+    async fn sync_all(&mut self) -> io::Result<()> {
+        File::sync_all(self).await
+    }
+
+    async fn set_len(&mut self, num_bytes: u64) -> io::Result<()> {
+        File::set_len(self, num_bytes).await
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn list(&self) -> io::Result<Vec<(String, u64)>> {
+        let mut files = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(&self.dir_path).await?;
+        while let Some(dir_entry) = read_dir.next_entry().await? {
+            if !dir_entry.file_type().await?.is_file() {
+                continue;
+            }
+            if let Some(file_name) = dir_entry.file_name().to_str() {
+                let num_bytes = dir_entry.metadata().await?.len();
+                files.push((file_name.to_string(), num_bytes));
+            }
+        }
+        Ok(files)
+    }
+
+    async fn create(&self, name: &str) -> io::Result<Box<dyn StorageFile>> {
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(self.dir_path.join(name))
+            .await?;
+        Ok(Box::new(file))
+    }
+
+    async fn open(&self, name: &str) -> io::Result<Box<dyn StorageFile>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.dir_path.join(name))
+            .await?;
+        Ok(Box::new(file))
+    }
+
+    async fn remove(&self, name: &str) -> io::Result<()> {
+        tokio::fs::remove_file(self.dir_path.join(name)).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        tokio::fs::rename(self.dir_path.join(from), self.dir_path.join(to)).await
+    }
+
+    async fn sync(&self) -> io::Result<()> {
+        // Directories cannot be opened, and do not need to be synced, on Windows.
+        #[cfg(unix)]
+        {
+            let dir = File::open(&self.dir_path).await?;
+            dir.sync_all().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::SeekFrom;
+
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_storage_locked() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::open(tmp_dir.path()).await.unwrap();
+        let lock_error = LocalStorage::open(tmp_dir.path()).await.err().unwrap();
+        assert_eq!(lock_error.kind(), io::ErrorKind::WouldBlock);
+        drop(storage);
+        LocalStorage::open(tmp_dir.path()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_storage() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::open(tmp_dir.path()).await.unwrap();
+        let mut file = storage.create("hello").await.unwrap();
+        file.write_all(b"hello world").await.unwrap();
+        file.flush().await.unwrap();
+        file.sync_all().await.unwrap();
+        let create_error = storage.create("hello").await.err().unwrap();
+        assert_eq!(create_error.kind(), io::ErrorKind::AlreadyExists);
+        storage.rename("hello", "hello2").await.unwrap();
+        storage.sync().await.unwrap();
+        let mut files = storage.list().await.unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![("hello2".to_string(), 11), ("lock".to_string(), 0)]
+        );
+        let mut file = storage.open("hello2").await.unwrap();
+        file.seek(SeekFrom::Start(6)).await.unwrap();
+        let mut buf = [0u8; 5];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"world");
+        let mut file = storage.open("hello2").await.unwrap();
+        file.set_len(5).await.unwrap();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).await.unwrap();
+        assert_eq!(&content, b"hello");
+        storage.remove("hello2").await.unwrap();
+        let remove_error = storage.remove("hello2").await.err().unwrap();
+        assert_eq!(remove_error.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright (C) 2022 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::storage::{Storage, StorageFile};
+
+type FileContent = Arc<Mutex<Vec<u8>>>;
+
+/// Storage of the files in memory, as byte vectors. Nothing is persisted.
+///
+/// The clones of a storage share its files, so that a log can be reopened
+/// from a clone of the storage it was opened with.
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+    files: Arc<Mutex<BTreeMap<String, FileContent>>>,
+}
+
+impl MemoryStorage {
+    fn file(&self, name: &str) -> io::Result<FileContent> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| not_found(name))
+    }
+}
+
+fn not_found(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("the file `{name}` does not exist"),
+    )
+}
+
+/// File of a `MemoryStorage`.
+///
+/// As on a local filesystem, a file removed remains readable and writable
+/// through the handles opened before its removal.
+struct MemoryFile {
+    content: FileContent,
+    position: u64,
+}
+
+impl MemoryFile {
+    fn new(content: FileContent) -> Self {
+        MemoryFile {
+            content,
+            position: 0,
+        }
+    }
+}
+
+impl AsyncRead for MemoryFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let file = self.get_mut();
+        let content = file.content.lock().unwrap();
+        let start = (file.position as usize).min(content.len());
+        let num_bytes = buf.remaining().min(content.len() - start);
+        buf.put_slice(&content[start..start + num_bytes]);
+        file.position += num_bytes as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for MemoryFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let file = self.get_mut();
+        let mut content = file.content.lock().unwrap();
+        let start = file.position as usize;
+        let end = start + buf.len();
+        if content.len() < end {
+            content.resize(end, 0u8);
+        }
+        content[start..end].copy_from_slice(buf);
+        file.position = end as u64;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for MemoryFile {
+    fn start_seek(self: Pin<&mut Self>, seek_from: SeekFrom) -> io::Result<()> {
+        let file = self.get_mut();
+        let new_position = match seek_from {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => {
+                let len = file.content.lock().unwrap().len() as u64;
+                len.checked_add_signed(delta)
+            }
+            SeekFrom::Current(delta) => file.position.checked_add_signed(delta),
+        };
+        file.position = new_position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            )
+        })?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+#[async_trait]
+impl StorageFile for MemoryFile {
+    async fn sync_data(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn sync_all(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn set_len(&mut self, num_bytes: u64) -> io::Result<()> {
+        self.content.lock().unwrap().resize(num_bytes as usize, 0u8);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn list(&self) -> io::Result<Vec<(String, u64)>> {
+        let files = self.files.lock().unwrap();
+        let file_names_and_sizes = files
+            .iter()
+            .map(|(name, content)| (name.clone(), content.lock().unwrap().len() as u64))
+            .collect();
+        Ok(file_names_and_sizes)
+    }
+
+    async fn create(&self, name: &str) -> io::Result<Box<dyn StorageFile>> {
+        let mut files = self.files.lock().unwrap();
+        if files.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("the file `{name}` already exists"),
+            ));
+        }
+        let content = FileContent::default();
+        files.insert(name.to_string(), content.clone());
+        Ok(Box::new(MemoryFile::new(content)))
+    }
+
+    async fn open(&self, name: &str) -> io::Result<Box<dyn StorageFile>> {
+        let content = self.file(name)?;
+        Ok(Box::new(MemoryFile::new(content)))
+    }
+
+    async fn remove(&self, name: &str) -> io::Result<()> {
+        self.files
+            .lock()
+            .unwrap()
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| not_found(name))
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let content = files.remove(from).ok_or_else(|| not_found(from))?;
+        files.insert(to.to_string(), content);
+        Ok(())
+    }
+
+    async fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_storage() {
+        let storage = MemoryStorage::default();
+        let mut file = storage.create("hello").await.unwrap();
+        file.write_all(b"hello world").await.unwrap();
+        let create_error = storage.create("hello").await.err().unwrap();
+        assert_eq!(create_error.kind(), io::ErrorKind::AlreadyExists);
+        storage.rename("hello", "hello2").await.unwrap();
+        // The clones of the storage share its files.
+        let storage_clone = storage.clone();
+        assert_eq!(
+            storage_clone.list().await.unwrap(),
+            vec![("hello2".to_string(), 11)]
+        );
+        let mut file = storage.open("hello2").await.unwrap();
+        file.seek(SeekFrom::Start(6)).await.unwrap();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).await.unwrap();
+        assert_eq!(&content, b"world");
+        file.set_len(5).await.unwrap();
+        file.seek(SeekFrom::End(0)).await.unwrap();
+        file.write_all(b"!").await.unwrap();
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).await.unwrap();
+        assert_eq!(&content, b"hello!");
+        storage.remove("hello2").await.unwrap();
+        let remove_error = storage.remove("hello2").await.err().unwrap();
+        assert_eq!(remove_error.kind(), io::ErrorKind::NotFound);
+        let open_error = storage.open("hello2").await.err().unwrap();
+        assert_eq!(open_error.kind(), io::ErrorKind::NotFound);
+        assert!(storage.list().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright (C) 2022 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Backends storing the files of a log.
+//!
+//! The log only goes through the `Storage` trait to access its files, so that it can be
+//! stored elsewhere than in a local directory. `LocalStorage` is the default implementation,
+//! and `MemoryStorage` keeps the files in memory, without persisting them. `FaultyStorage`
+//! wraps a storage to inject IO errors and crashes in tests.
+
+use std::io;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
+
+mod faulty;
+mod local;
+mod memory;
+
+pub use self::faulty::{Fault, FaultyStorage};
+pub use self::local::LocalStorage;
+pub use self::memory::MemoryStorage;
+
+/// File of a `Storage`, read and written through the tokio IO traits.
+#[async_trait]
+pub trait StorageFile: AsyncRead + AsyncWrite + AsyncSeek + Send + Sync + Unpin {
+    /// Makes the content of the file durable, without its metadata.
+    async fn sync_data(&mut self) -> io::Result<()>;
+
+    /// Makes the content and the metadata of the file durable.
+    async fn sync_all(&mut self) -> io::Result<()>;
+
+    /// Truncates or extends the file to `num_bytes`.
+    async fn set_len(&mut self, num_bytes: u64) -> io::Result<()>;
+}
+
+/// Flat namespace of files, in which the log stores its files and its checkpoint.
+///
+/// A storage is expected to be used by a single log at a time.
+#[async_trait]
+pub trait Storage: Send + Sync + 'static {
+    /// Returns the name and the number of bytes of each file.
+    async fn list(&self) -> io::Result<Vec<(String, u64)>>;
+
+    /// Creates a new empty file, opened for writing.
+    ///
+    /// Fails with an `io::ErrorKind::AlreadyExists` error if the file already exists.
+    async fn create(&self, name: &str) -> io::Result<Box<dyn StorageFile>>;
+
+    /// Opens an existing file for reading and writing, positioned at its beginning.
+    ///
+    /// Fails with an `io::ErrorKind::NotFound` error if the file does not exist.
+    async fn open(&self, name: &str) -> io::Result<Box<dyn StorageFile>>;
+
+    /// Removes a file.
+    ///
+    /// Fails with an `io::ErrorKind::NotFound` error if the file does not exist.
+    async fn remove(&self, name: &str) -> io::Result<()>;
+
+    /// Renames a file, replacing the destination if it exists.
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()>;
+
+    /// Makes the creation, the removal and the renaming of files durable.
+    async fn sync(&self) -> io::Result<()>;
+}
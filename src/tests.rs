@@ -1,14 +1,52 @@
-use crate::MultiRecordLog;
+use std::sync::Arc;
+use std::time::Duration;
 
-fn read_all_records<'a>(multi_record_log: &'a MultiRecordLog, queue: &str) -> Vec<&'a [u8]> {
-    let mut records = Vec::new();
-    let mut next_pos = u64::default();
-    for (pos, payload) in multi_record_log.range(queue, next_pos..).unwrap() {
-        assert_eq!(pos, next_pos);
-        records.push(payload);
-        next_pos += 1;
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use crate::checkpoint::serialize_checkpoint;
+use crate::compression::Codec;
+use crate::error::{
+    AppendError, CheckpointError, CreateQueueError, DeleteQueueError, ExceededLimit, RangeError,
+    TruncateError,
+};
+use crate::mem::MemQueues;
+use crate::position::FileNumber;
+use crate::record::ReadRecordError;
+use crate::rolling::{Record, RecordLogReader, RolloverPolicy};
+use crate::storage::{Fault, FaultyStorage, LocalStorage, MemoryStorage, Storage};
+#[cfg(all(feature = "lz4", feature = "zstd"))]
+use crate::Compression;
+use crate::{
+    CapacityLimits, MultiRecordLog, MultiRecordLogOptions, RecoveryMode, SharedMultiRecordLog,
+    SyncPolicy,
+};
+
+fn read_all_records(multi_record_log: &MultiRecordLog, queue: &str) -> Vec<Vec<u8>> {
+    let mut payloads = Vec::new();
+    for (expected_pos, (pos, payload)) in (0u64..).zip(multi_record_log.range(queue, ..).unwrap()) {
+        assert_eq!(pos, expected_pos);
+        payloads.push(payload.to_vec());
+    }
+    payloads
+}
+
+// Same as `read_all_records`, for queues holding records spilled.
+async fn read_all_records_stream(multi_record_log: &MultiRecordLog, queue: &str) -> Vec<Vec<u8>> {
+    let records: Vec<(u64, Vec<u8>)> = multi_record_log
+        .range_stream(queue, ..)
+        .unwrap()
+        .map_ok(|(position, payload)| (position, payload.into_owned()))
+        .try_collect()
+        .await
+        .unwrap();
+    let mut payloads = Vec::new();
+    for (expected_pos, (pos, payload)) in (0u64..).zip(records) {
+        assert_eq!(pos, expected_pos);
+        payloads.push(payload);
     }
-    records
+    payloads
 }
 
 #[tokio::test]
@@ -67,6 +105,32 @@ async fn test_multi_record_log() {
     }
 }
 
+#[tokio::test]
+async fn test_multi_record_log_truncate_up_to_last_record() {
+    let tempdir = tempfile::tempdir().unwrap();
+    {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        multi_record_log.create_queue("queue").await.unwrap();
+        multi_record_log
+            .append_records("queue", None, [&b"hello"[..], b"happy"].iter().copied())
+            .await
+            .unwrap();
+        // Truncating up to the last record empties the queue, without panicking.
+        multi_record_log.truncate("queue", 1).await.unwrap();
+        assert!(read_all_records(&multi_record_log, "queue").is_empty());
+        assert_eq!(
+            multi_record_log
+                .append_record("queue", None, b"tax")
+                .await
+                .unwrap(),
+            Some(2)
+        );
+    }
+    let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    let records: Vec<(u64, &[u8])> = multi_record_log.range("queue", ..).unwrap().collect();
+    assert_eq!(records, [(2, &b"tax"[..])]);
+}
+
 #[tokio::test]
 async fn test_multi_record_position_known_after_truncate() {
     let tempdir = tempfile::tempdir().unwrap();
@@ -108,3 +172,1343 @@ async fn test_multi_record_position_known_after_truncate() {
         );
     }
 }
+
+#[tokio::test]
+async fn test_multi_record_log_sync_policies() {
+    let sync_policies = [
+        SyncPolicy::OnAppend,
+        SyncPolicy::OnDelay(Duration::from_millis(10)),
+        SyncPolicy::EveryNBytes(10),
+        SyncPolicy::Never,
+    ];
+    for sync_policy in sync_policies {
+        let tempdir = tempfile::tempdir().unwrap();
+        let options = MultiRecordLogOptions::default().with_sync_policy(sync_policy);
+        {
+            let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+                .await
+                .unwrap();
+            multi_record_log.create_queue("queue").await.unwrap();
+            for payload in [&b"hello"[..], b"happy", b"tax"] {
+                multi_record_log
+                    .append_record("queue", None, payload)
+                    .await
+                    .unwrap();
+            }
+        }
+        {
+            let multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+                .await
+                .unwrap();
+            assert_eq!(
+                &read_all_records(&multi_record_log, "queue"),
+                &[b"hello".as_slice(), b"happy".as_slice(), b"tax".as_slice()]
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_multi_record_log_append_records() {
+    let tempdir = tempfile::tempdir().unwrap();
+    {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        multi_record_log.create_queue("queue").await.unwrap();
+        let payloads = [&b"hello"[..], b"happy", b"tax"];
+        assert_eq!(
+            multi_record_log
+                .append_records("queue", None, payloads.iter().copied())
+                .await
+                .unwrap(),
+            Some(0..3)
+        );
+        assert_eq!(
+            multi_record_log
+                .append_records("queue", Some(3), [&b"payer"[..], b"!"].iter().copied())
+                .await
+                .unwrap(),
+            Some(3..5)
+        );
+        // The same batch is only appended once.
+        assert_eq!(
+            multi_record_log
+                .append_records("queue", Some(3), [&b"payer"[..], b"!"].iter().copied())
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(matches!(
+            multi_record_log
+                .append_records("queue", Some(2), [&b"payer"[..], b"!"].iter().copied())
+                .await,
+            Err(AppendError::Past)
+        ));
+        assert!(matches!(
+            multi_record_log
+                .append_records("queue", Some(6), [&b"payer"[..]].iter().copied())
+                .await,
+            Err(AppendError::Future)
+        ));
+        assert_eq!(
+            multi_record_log
+                .append_records("queue", None, std::iter::empty())
+                .await
+                .unwrap(),
+            None
+        );
+    }
+    {
+        let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        assert_eq!(
+            &read_all_records(&multi_record_log, "queue"),
+            &[
+                b"hello".as_slice(),
+                b"happy".as_slice(),
+                b"tax".as_slice(),
+                b"payer".as_slice(),
+                b"!".as_slice()
+            ]
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_multi_record_log_unflushed_records_not_visible() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    multi_record_log.create_queue("queue").await.unwrap();
+    assert_eq!(
+        multi_record_log
+            .append_record_without_flush("queue", None, b"hello")
+            .await
+            .unwrap(),
+        Some(0)
+    );
+    assert_eq!(
+        multi_record_log
+            .append_record_without_flush("queue", None, b"happy")
+            .await
+            .unwrap(),
+        Some(1)
+    );
+    assert!(matches!(
+        multi_record_log
+            .append_record_without_flush("queue", Some(3), b"tax")
+            .await,
+        Err(AppendError::Future)
+    ));
+    assert!(read_all_records(&multi_record_log, "queue").is_empty());
+    multi_record_log.flush().await.unwrap();
+    assert_eq!(
+        &read_all_records(&multi_record_log, "queue"),
+        &[b"hello".as_slice(), b"happy".as_slice()]
+    );
+}
+
+#[tokio::test]
+async fn test_multi_record_log_poisoned_after_io_error() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let log_dir = tempdir.path().join("log");
+    std::fs::create_dir(&log_dir).unwrap();
+    let mut multi_record_log = MultiRecordLog::open(&log_dir).await.unwrap();
+    std::fs::remove_dir_all(&log_dir).unwrap();
+    assert!(matches!(
+        multi_record_log.create_queue("queue").await,
+        Err(CreateQueueError::IoError(_))
+    ));
+    assert!(multi_record_log.is_poisoned());
+    assert!(multi_record_log.range("queue", ..).is_err());
+    assert!(matches!(
+        multi_record_log.create_queue("queue").await,
+        Err(CreateQueueError::Poisoned)
+    ));
+    assert!(matches!(
+        multi_record_log
+            .append_record("queue", None, b"hello")
+            .await,
+        Err(AppendError::Poisoned)
+    ));
+    assert!(matches!(
+        multi_record_log.truncate("queue", 0).await,
+        Err(TruncateError::Poisoned)
+    ));
+}
+
+#[tokio::test]
+async fn test_multi_record_log_delete_queue() {
+    let tempdir = tempfile::tempdir().unwrap();
+    {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        multi_record_log.create_queue("queue1").await.unwrap();
+        multi_record_log.create_queue("queue2").await.unwrap();
+        multi_record_log
+            .append_record("queue1", None, b"hello")
+            .await
+            .unwrap();
+        multi_record_log
+            .append_record("queue2", None, b"maitre")
+            .await
+            .unwrap();
+    }
+    {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        multi_record_log.truncate("queue2", 0).await.unwrap();
+        assert_eq!(multi_record_log.num_files(), 2);
+        // The first file was only retained by `queue1`.
+        multi_record_log.delete_queue("queue1").await.unwrap();
+        assert_eq!(multi_record_log.num_files(), 1);
+        assert!(matches!(
+            multi_record_log.delete_queue("queue1").await,
+            Err(DeleteQueueError::MissingQueue(_))
+        ));
+        assert!(multi_record_log.range("queue1", ..).is_err());
+    }
+    {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        assert!(matches!(
+            multi_record_log
+                .append_record("queue1", None, b"hello")
+                .await,
+            Err(AppendError::MissingQueue(_))
+        ));
+        assert_eq!(
+            multi_record_log
+                .append_record("queue2", None, b"corbeau")
+                .await
+                .unwrap(),
+            Some(1)
+        );
+        multi_record_log.create_queue("queue1").await.unwrap();
+        assert_eq!(
+            multi_record_log
+                .append_record("queue1", None, b"happy")
+                .await
+                .unwrap(),
+            Some(0)
+        );
+    }
+    {
+        let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        assert_eq!(&read_all_records(&multi_record_log, "queue1"), &[b"happy"]);
+    }
+}
+
+#[tokio::test]
+async fn test_multi_record_log_usage() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let disk_usage = {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        assert_eq!(multi_record_log.disk_usage(), 0);
+        assert_eq!(multi_record_log.memory_usage(), 0);
+        multi_record_log.create_queue("queue").await.unwrap();
+        let disk_usage = multi_record_log.disk_usage();
+        assert!(disk_usage > 0);
+        multi_record_log
+            .append_records("queue", None, [&b"hello"[..], b"happy"].iter().copied())
+            .await
+            .unwrap();
+        assert!(multi_record_log.disk_usage() > disk_usage + 10);
+        assert!(multi_record_log.memory_usage() >= 10);
+        let queue_stats = multi_record_log.queue_stats("queue").unwrap();
+        assert_eq!(queue_stats.num_records, 2);
+        assert_eq!(queue_stats.num_bytes, 10);
+        assert_eq!(queue_stats.first_position, Some(0));
+        assert_eq!(queue_stats.last_position, Some(1));
+        assert!(multi_record_log.queue_stats("missing").is_err());
+        // The checkpoint is accounted for.
+        let disk_usage = multi_record_log.disk_usage();
+        multi_record_log.checkpoint().await.unwrap();
+        let checkpoint_num_bytes = std::fs::metadata(tempdir.path().join("checkpoint"))
+            .unwrap()
+            .len();
+        assert_eq!(
+            multi_record_log.disk_usage(),
+            disk_usage + checkpoint_num_bytes
+        );
+        multi_record_log.disk_usage()
+    };
+    let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    assert_eq!(multi_record_log.disk_usage(), disk_usage);
+    multi_record_log.truncate("queue", 1).await.unwrap();
+    // Only the new file, holding the truncation, is left.
+    assert!(multi_record_log.disk_usage() < disk_usage);
+    assert_eq!(
+        multi_record_log.queue_stats("queue").unwrap().num_records,
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_multi_record_log_capacity_limits() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    multi_record_log.set_capacity_limits(CapacityLimits {
+        max_memory_num_bytes: Some(12),
+        max_queue_num_bytes: Some(8),
+        max_num_files: Some(1),
+        max_disk_usage: None,
+    });
+    multi_record_log.create_queue("queue1").await.unwrap();
+    multi_record_log.create_queue("queue2").await.unwrap();
+    multi_record_log
+        .append_record("queue1", None, b"hello")
+        .await
+        .unwrap();
+    assert!(matches!(
+        multi_record_log
+            .append_record("queue1", None, b"happy")
+            .await,
+        Err(AppendError::Full {
+            limit: ExceededLimit::QueueNumBytes
+        })
+    ));
+    // The batch is rejected as a whole.
+    assert!(matches!(
+        multi_record_log
+            .append_records("queue2", None, [&b"maitre"[..], b"corbeau"].iter().copied())
+            .await,
+        Err(AppendError::Full {
+            limit: ExceededLimit::MemoryNumBytes
+        })
+    ));
+    assert_eq!(
+        multi_record_log
+            .append_record("queue2", None, b"maitre")
+            .await
+            .unwrap(),
+        Some(0)
+    );
+    multi_record_log.truncate("queue1", 0).await.unwrap();
+    assert_eq!(
+        multi_record_log
+            .append_record("queue1", None, b"happy")
+            .await
+            .unwrap(),
+        Some(1)
+    );
+    assert_eq!(multi_record_log.num_files(), 1);
+    drop(multi_record_log);
+
+    // Reopening the log requires a new file.
+    let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    multi_record_log.set_capacity_limits(CapacityLimits {
+        max_num_files: Some(1),
+        ..Default::default()
+    });
+    assert!(matches!(
+        multi_record_log.append_record("queue1", None, b"tax").await,
+        Err(AppendError::Full {
+            limit: ExceededLimit::NumFiles
+        })
+    ));
+    assert!(!multi_record_log.is_poisoned());
+    assert_eq!(&read_all_records(&multi_record_log, "queue2"), &[b"maitre"]);
+    multi_record_log.set_capacity_limits(CapacityLimits {
+        max_disk_usage: Some(multi_record_log.disk_usage() + 4),
+        ..Default::default()
+    });
+    assert!(matches!(
+        multi_record_log
+            .append_record("queue1", None, b"happy")
+            .await,
+        Err(AppendError::Full {
+            limit: ExceededLimit::DiskUsage
+        })
+    ));
+    multi_record_log.set_capacity_limits(CapacityLimits::default());
+    multi_record_log
+        .append_record("queue1", None, b"happy")
+        .await
+        .unwrap();
+    // The checkpoint counts against the limit as well.
+    multi_record_log.set_capacity_limits(CapacityLimits {
+        max_disk_usage: Some(multi_record_log.disk_usage() + 3),
+        ..Default::default()
+    });
+    multi_record_log.checkpoint().await.unwrap();
+    assert!(matches!(
+        multi_record_log.append_record("queue1", None, b"tax").await,
+        Err(AppendError::Full {
+            limit: ExceededLimit::DiskUsage
+        })
+    ));
+}
+
+#[tokio::test]
+async fn test_multi_record_log_rollover_num_bytes() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let options = MultiRecordLogOptions::default().with_rollover_num_bytes(100);
+    let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+        .await
+        .unwrap();
+    multi_record_log.create_queue("queue1").await.unwrap();
+    multi_record_log.create_queue("queue2").await.unwrap();
+    // Each record fills up its file.
+    let payload = [0u8; 100];
+    for _ in 0..4 {
+        multi_record_log
+            .append_record("queue1", None, &payload)
+            .await
+            .unwrap();
+    }
+    multi_record_log
+        .append_record("queue2", None, &payload)
+        .await
+        .unwrap();
+    assert_eq!(multi_record_log.num_files(), 5);
+    multi_record_log.truncate("queue1", 3).await.unwrap();
+    // Only the file holding the record of `queue2`, and the one holding the truncation,
+    // are left.
+    assert_eq!(multi_record_log.num_files(), 2);
+    multi_record_log.truncate("queue2", 0).await.unwrap();
+    assert_eq!(multi_record_log.num_files(), 1);
+    drop(multi_record_log);
+    let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    assert_eq!(
+        multi_record_log
+            .append_record("queue1", None, b"hello")
+            .await
+            .unwrap(),
+        Some(4)
+    );
+}
+
+#[tokio::test]
+async fn test_multi_record_log_rollover_delay() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let options = MultiRecordLogOptions::default().with_rollover_delay(Duration::from_millis(10));
+    let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+        .await
+        .unwrap();
+    multi_record_log.create_queue("queue").await.unwrap();
+    multi_record_log
+        .append_record("queue", None, b"hello")
+        .await
+        .unwrap();
+    assert_eq!(multi_record_log.num_files(), 1);
+    std::thread::sleep(Duration::from_millis(20));
+    multi_record_log
+        .append_record("queue", None, b"happy")
+        .await
+        .unwrap();
+    assert_eq!(multi_record_log.num_files(), 2);
+}
+
+#[tokio::test]
+async fn test_multi_record_log_compact() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let options = MultiRecordLogOptions::default().with_rollover_num_bytes(100);
+    {
+        let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+            .await
+            .unwrap();
+        multi_record_log.create_queue("idle").await.unwrap();
+        multi_record_log.create_queue("busy").await.unwrap();
+        multi_record_log.create_queue("empty").await.unwrap();
+        multi_record_log
+            .append_record("idle", Some(3), b"hello")
+            .await
+            .unwrap();
+        for position in 0..10 {
+            multi_record_log
+                .append_record("busy", None, &[0u8; 100])
+                .await
+                .unwrap();
+            multi_record_log.truncate("busy", position).await.unwrap();
+        }
+        // The idle queue retains all of the files.
+        let num_files = multi_record_log.num_files();
+        assert!(num_files > 5);
+        multi_record_log.compact().await.unwrap();
+        assert!(multi_record_log.num_files() < 3);
+        assert!(multi_record_log.disk_usage() < 500);
+        // Nothing left to compact.
+        multi_record_log.compact().await.unwrap();
+    }
+    let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    let idle_records: Vec<(u64, &[u8])> = multi_record_log.range("idle", ..).unwrap().collect();
+    assert_eq!(&idle_records, &[(3, &b"hello"[..])]);
+    assert_eq!(multi_record_log.range("busy", ..).unwrap().count(), 0);
+    for (queue, expected_position) in [("idle", 4), ("busy", 10), ("empty", 0)] {
+        assert_eq!(
+            multi_record_log
+                .append_record(queue, None, b"happy")
+                .await
+                .unwrap(),
+            Some(expected_position)
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_multi_record_log_interrupted_compaction_ignored() {
+    let tempdir = tempfile::tempdir().unwrap();
+    {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        multi_record_log.create_queue("queue").await.unwrap();
+        multi_record_log
+            .append_records("queue", None, [&b"hello"[..], b"happy"].iter().copied())
+            .await
+            .unwrap();
+    }
+    {
+        // Simulates a compaction interrupted after its first record.
+        let mut record_log_reader = RecordLogReader::open(tempdir.path()).await.unwrap();
+        while record_log_reader.read_record().await.unwrap().is_some() {}
+        let mut record_log_writer = record_log_reader
+            .into_writer(SyncPolicy::default(), RolloverPolicy::default(), false)
+            .await
+            .unwrap();
+        record_log_writer.roll_if_needed().await.unwrap();
+        for record in [
+            Record::CompactionStart {
+                position: 0,
+                queue: "queue",
+            },
+            Record::AppendRecord {
+                position: 0,
+                queue: "queue",
+                timestamp: None,
+                codec: Codec::None,
+                payload: b"hello",
+            },
+        ] {
+            record_log_writer.write_record(record).await.unwrap();
+        }
+        record_log_writer.flush().await.unwrap();
+    }
+    {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        assert_eq!(
+            &read_all_records(&multi_record_log, "queue"),
+            &[b"hello".as_slice(), b"happy".as_slice()]
+        );
+        assert_eq!(
+            multi_record_log
+                .append_record("queue", None, b"tax")
+                .await
+                .unwrap(),
+            Some(2)
+        );
+    }
+    let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    assert_eq!(read_all_records(&multi_record_log, "queue").len(), 3);
+}
+
+#[tokio::test]
+async fn test_multi_record_log_delete_unknown_queue() {
+    let tempdir = tempfile::tempdir().unwrap();
+    {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        multi_record_log.create_queue("queue").await.unwrap();
+        multi_record_log
+            .append_record("queue", None, b"hello")
+            .await
+            .unwrap();
+    }
+    {
+        // Deletes a queue that was never created.
+        let mut record_log_reader = RecordLogReader::open(tempdir.path()).await.unwrap();
+        while record_log_reader.read_record().await.unwrap().is_some() {}
+        let mut record_log_writer = record_log_reader
+            .into_writer(SyncPolicy::default(), RolloverPolicy::default(), false)
+            .await
+            .unwrap();
+        record_log_writer.roll_if_needed().await.unwrap();
+        record_log_writer
+            .write_record(Record::DeleteQueue { queue: "ghost" })
+            .await
+            .unwrap();
+        record_log_writer.flush().await.unwrap();
+    }
+    assert!(matches!(
+        MultiRecordLog::open(tempdir.path()).await.err().unwrap(),
+        ReadRecordError::Corruption
+    ));
+    {
+        let options = MultiRecordLogOptions::default().with_recovery_mode(RecoveryMode::BestEffort);
+        let multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+            .await
+            .unwrap();
+        let corruptions = &multi_record_log.recovery_report().corruptions;
+        assert_eq!(corruptions.len(), 1);
+        assert_eq!(corruptions[0].file_number, FileNumber::from(2));
+        assert_eq!(corruptions[0].queue.as_deref(), Some("ghost"));
+        assert_eq!(corruptions[0].num_records_lost, 0);
+        assert_eq!(read_all_records(&multi_record_log, "queue"), [b"hello"]);
+    }
+    // Once the first file is removed, the creation of the queue may have been in it.
+    std::fs::remove_file(tempdir.path().join("wal-00000000000000000001")).unwrap();
+    let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    assert!(multi_record_log.recovery_report().is_empty());
+}
+
+#[tokio::test]
+async fn test_multi_record_log_checkpoint() {
+    let tempdir = tempfile::tempdir().unwrap();
+    {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        multi_record_log.create_queue("queue1").await.unwrap();
+        multi_record_log.create_queue("queue2").await.unwrap();
+        multi_record_log
+            .append_records("queue1", None, [&b"hello"[..], b"happy"].iter().copied())
+            .await
+            .unwrap();
+        multi_record_log
+            .append_record("queue2", None, b"tax")
+            .await
+            .unwrap();
+        multi_record_log.checkpoint().await.unwrap();
+        multi_record_log
+            .append_record("queue1", None, b"payer")
+            .await
+            .unwrap();
+        multi_record_log.truncate("queue1", 0).await.unwrap();
+        multi_record_log.delete_queue("queue2").await.unwrap();
+        multi_record_log.create_queue("queue3").await.unwrap();
+    }
+    let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    let records: Vec<(u64, &[u8])> = multi_record_log.range("queue1", ..).unwrap().collect();
+    assert_eq!(&records, &[(1, &b"happy"[..]), (2, &b"payer"[..])]);
+    assert!(multi_record_log.range("queue2", ..).is_err());
+    assert_eq!(multi_record_log.range("queue3", ..).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn test_multi_record_log_open_from_checkpoint() {
+    let tempdir = tempfile::tempdir().unwrap();
+    {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        multi_record_log.create_queue("queue").await.unwrap();
+        multi_record_log
+            .append_record("queue", None, b"hello")
+            .await
+            .unwrap();
+    }
+    let wal_num_bytes = std::fs::metadata(tempdir.path().join("wal-00000000000000000001"))
+        .unwrap()
+        .len();
+    // A checkpoint that does not match the records of the log shows which one is used.
+    let mut mem_queues = MemQueues::default();
+    mem_queues.touch("checkpointed", 5).unwrap();
+    let checkpoint = serialize_checkpoint(1.into(), wal_num_bytes, &mem_queues);
+    std::fs::write(tempdir.path().join("checkpoint"), &checkpoint).unwrap();
+    {
+        let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        assert!(multi_record_log.queue_stats("checkpointed").is_ok());
+        assert!(multi_record_log.range("queue", ..).is_err());
+    }
+    // A corrupted checkpoint is ignored.
+    std::fs::write(tempdir.path().join("checkpoint"), b"garbage").unwrap();
+    let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    assert_eq!(read_all_records(&multi_record_log, "queue"), &[b"hello"]);
+}
+
+#[tokio::test]
+async fn test_multi_record_log_checkpoint_without_payloads() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let payload = vec![7u8; 100_000];
+    {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        multi_record_log.create_queue("queue").await.unwrap();
+        multi_record_log
+            .append_record("queue", None, &payload)
+            .await
+            .unwrap();
+        multi_record_log.checkpoint().await.unwrap();
+    }
+    // The checkpoint only locates the record in the log.
+    let checkpoint_num_bytes = std::fs::metadata(tempdir.path().join("checkpoint"))
+        .unwrap()
+        .len();
+    assert!(checkpoint_num_bytes < 100);
+    let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    assert_eq!(
+        read_all_records(&multi_record_log, "queue"),
+        &[&payload[..]]
+    );
+}
+
+#[tokio::test]
+async fn test_multi_record_log_checkpoint_files_removed() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let options = MultiRecordLogOptions::default().with_rollover_num_bytes(1);
+    {
+        // Every record is written to a new file.
+        let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+            .await
+            .unwrap();
+        multi_record_log.create_queue("queue1").await.unwrap();
+        multi_record_log.create_queue("queue2").await.unwrap();
+        multi_record_log
+            .append_record("queue1", None, b"hello")
+            .await
+            .unwrap();
+        multi_record_log
+            .append_record("queue2", None, b"happy")
+            .await
+            .unwrap();
+        multi_record_log.checkpoint().await.unwrap();
+        // Removes the file holding the record of queue1, but not the checkpointed file.
+        multi_record_log.truncate("queue1", 0).await.unwrap();
+    }
+    {
+        let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+            .await
+            .unwrap();
+        assert_eq!(multi_record_log.range("queue1", ..).unwrap().count(), 0);
+        assert_eq!(read_all_records(&multi_record_log, "queue2"), &[b"happy"]);
+        // Removes the checkpointed file.
+        multi_record_log
+            .append_record("queue2", None, b"tax")
+            .await
+            .unwrap();
+        multi_record_log.truncate("queue2", 0).await.unwrap();
+    }
+    let multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+        .await
+        .unwrap();
+    assert_eq!(multi_record_log.range("queue1", ..).unwrap().count(), 0);
+    let records: Vec<(u64, &[u8])> = multi_record_log.range("queue2", ..).unwrap().collect();
+    assert_eq!(&records, &[(1, &b"tax"[..])]);
+}
+
+#[tokio::test]
+async fn test_multi_record_log_checkpoint_interval() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let options = MultiRecordLogOptions::default().with_checkpoint_interval(Duration::ZERO);
+    let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+        .await
+        .unwrap();
+    multi_record_log.create_queue("queue").await.unwrap();
+    assert!(!tempdir.path().join("checkpoint").exists());
+    multi_record_log
+        .append_record("queue", None, b"hello")
+        .await
+        .unwrap();
+    assert!(tempdir.path().join("checkpoint").exists());
+}
+
+#[tokio::test]
+async fn test_multi_record_log_checkpoint_interval_failure() {
+    let tempdir = tempfile::tempdir().unwrap();
+    // The temporary file of the checkpoint cannot be removed, so writing it fails.
+    std::fs::create_dir(tempdir.path().join("checkpoint.tmp")).unwrap();
+    let options = MultiRecordLogOptions::default().with_checkpoint_interval(Duration::ZERO);
+    let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+        .await
+        .unwrap();
+    multi_record_log.create_queue("queue").await.unwrap();
+    // The record is appended, even though the periodic checkpoint fails.
+    assert_eq!(
+        multi_record_log
+            .append_record("queue", None, b"hello")
+            .await
+            .unwrap(),
+        Some(0)
+    );
+    assert!(!tempdir.path().join("checkpoint").exists());
+    assert!(!multi_record_log.is_poisoned());
+    // An explicit checkpoint returns the error.
+    assert!(matches!(
+        multi_record_log.checkpoint().await,
+        Err(CheckpointError::IoError(_))
+    ));
+    assert!(!multi_record_log.is_poisoned());
+}
+
+#[tokio::test]
+async fn test_multi_record_log_best_effort_recovery() {
+    let tempdir = tempfile::tempdir().unwrap();
+    {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        multi_record_log.create_queue("queue").await.unwrap();
+        for payload in [b"aaaa", b"bbbb", b"cccc", b"dddd"] {
+            multi_record_log
+                .append_record("queue", None, payload)
+                .await
+                .unwrap();
+        }
+    }
+    let wal_filepath = tempdir.path().join("wal-00000000000000000001");
+    let mut wal = std::fs::read(&wal_filepath).unwrap();
+    let corrupted_offset = wal.windows(4).position(|window| window == b"bbbb").unwrap();
+    wal[corrupted_offset] = b'x';
+    std::fs::write(&wal_filepath, &wal).unwrap();
+
+    assert!(matches!(
+        MultiRecordLog::open(tempdir.path()).await,
+        Err(ReadRecordError::Corruption)
+    ));
+    let options = MultiRecordLogOptions::default().with_recovery_mode(RecoveryMode::BestEffort);
+    let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+        .await
+        .unwrap();
+    // The queue restarts after the lost record.
+    let records: Vec<(u64, &[u8])> = multi_record_log.range("queue", ..).unwrap().collect();
+    assert_eq!(&records, &[(2, &b"cccc"[..]), (3, &b"dddd"[..])]);
+    let recovery_report = multi_record_log.recovery_report();
+    assert_eq!(recovery_report.corruptions.len(), 2);
+    let frame_corruption = &recovery_report.corruptions[0];
+    assert_eq!(frame_corruption.file_number, 1.into());
+    assert!(frame_corruption.offset < corrupted_offset as u64);
+    assert_eq!(frame_corruption.queue, None);
+    let gap_corruption = &recovery_report.corruptions[1];
+    assert!(gap_corruption.offset > corrupted_offset as u64);
+    assert_eq!(gap_corruption.queue.as_deref(), Some("queue"));
+    assert_eq!(recovery_report.num_records_lost(), 2);
+    assert_eq!(recovery_report.queues(), &["queue"]);
+    assert_eq!(
+        multi_record_log
+            .append_record("queue", None, b"eeee")
+            .await
+            .unwrap(),
+        Some(4)
+    );
+}
+
+#[tokio::test]
+async fn test_multi_record_log_resume_last_file() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let options = MultiRecordLogOptions::default().with_resume_last_file(true);
+    for payload in [b"hello", b"happy"] {
+        let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+            .await
+            .unwrap();
+        if multi_record_log.range("queue", ..).is_err() {
+            multi_record_log.create_queue("queue").await.unwrap();
+        }
+        multi_record_log
+            .append_record("queue", None, payload)
+            .await
+            .unwrap();
+        assert_eq!(multi_record_log.num_files(), 1);
+    }
+    let multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+        .await
+        .unwrap();
+    assert_eq!(
+        read_all_records(&multi_record_log, "queue"),
+        &[b"hello", b"happy"]
+    );
+}
+
+#[tokio::test]
+async fn test_multi_record_log_range_bytes() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    multi_record_log.create_queue("queue").await.unwrap();
+    multi_record_log
+        .append_records("queue", None, [&b"hello"[..], b"happy"].iter().copied())
+        .await
+        .unwrap();
+    let records: Vec<(u64, Bytes)> = multi_record_log
+        .range_bytes("queue", 1..)
+        .unwrap()
+        .collect();
+    // The payloads outlive the truncation of their records.
+    multi_record_log.truncate("queue", 1).await.unwrap();
+    assert_eq!(multi_record_log.range("queue", ..).unwrap().count(), 0);
+    assert_eq!(&records, &[(1, Bytes::from_static(b"happy"))]);
+}
+
+#[tokio::test]
+async fn test_multi_record_log_spill() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let options = MultiRecordLogOptions::default()
+        .with_rollover_num_bytes(100_000)
+        .with_spill_num_bytes(50_000);
+    // Some of the records span several blocks.
+    let payloads: Vec<Vec<u8>> = (0..40u8)
+        .map(|i| vec![i; 1_000 + 1_000 * (i as usize % 4) * (i as usize % 3) * 4])
+        .collect();
+    let num_bytes: usize = payloads.iter().map(Vec::len).sum();
+    let expected_records = |parity: usize| -> Vec<(u64, Vec<u8>)> {
+        (0u64..)
+            .zip(payloads.iter().skip(parity).step_by(2).cloned())
+            .collect()
+    };
+    async fn records(multi_record_log: &MultiRecordLog, queue: &str) -> Vec<(u64, Vec<u8>)> {
+        multi_record_log
+            .range_stream(queue, ..)
+            .unwrap()
+            .map_ok(|(position, payload)| (position, payload.into_owned()))
+            .try_collect()
+            .await
+            .unwrap()
+    }
+    {
+        let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+            .await
+            .unwrap();
+        multi_record_log.create_queue("even").await.unwrap();
+        multi_record_log.create_queue("odd").await.unwrap();
+        for (i, payload) in payloads.iter().enumerate() {
+            let queue = if i % 2 == 0 { "even" } else { "odd" };
+            multi_record_log
+                .append_record(queue, None, payload)
+                .await
+                .unwrap();
+        }
+        assert!(multi_record_log.num_files() > 1);
+        assert!(multi_record_log.memory_usage() < num_bytes / 2);
+        assert_eq!(
+            multi_record_log.queue_stats("even").unwrap().num_bytes
+                + multi_record_log.queue_stats("odd").unwrap().num_bytes,
+            num_bytes
+        );
+        assert_eq!(
+            records(&multi_record_log, "even").await,
+            expected_records(0)
+        );
+        assert_eq!(records(&multi_record_log, "odd").await, expected_records(1));
+        let records: Vec<(u64, Bytes)> = multi_record_log
+            .range_bytes_stream("odd", 18..)
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            &records,
+            &[
+                (18, Bytes::from(payloads[37].clone())),
+                (19, Bytes::from(payloads[39].clone()))
+            ]
+        );
+        multi_record_log.checkpoint().await.unwrap();
+    }
+    {
+        // The records spilled are restored from the checkpoint.
+        let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+            .await
+            .unwrap();
+        assert!(multi_record_log.memory_usage() < num_bytes / 2);
+        assert_eq!(
+            records(&multi_record_log, "even").await,
+            expected_records(0)
+        );
+        multi_record_log.truncate("even", 9).await.unwrap();
+        multi_record_log.compact().await.unwrap();
+        assert_eq!(
+            records(&multi_record_log, "even").await,
+            expected_records(0)[10..]
+        );
+        assert_eq!(records(&multi_record_log, "odd").await, expected_records(1));
+    }
+    std::fs::remove_file(tempdir.path().join("checkpoint")).unwrap();
+    // Spilled records are also read back after replaying the whole log, whether
+    // spilling is enabled or not.
+    for options in [options, MultiRecordLogOptions::default()] {
+        let multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+            .await
+            .unwrap();
+        assert_eq!(
+            records(&multi_record_log, "even").await,
+            expected_records(0)[10..]
+        );
+        assert_eq!(records(&multi_record_log, "odd").await, expected_records(1));
+    }
+}
+
+#[tokio::test]
+async fn test_multi_record_log_timestamps() {
+    let tempdir = tempfile::tempdir().unwrap();
+    {
+        let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+        multi_record_log.create_queue("queue").await.unwrap();
+        multi_record_log
+            .append_record("queue", None, b"untimestamped")
+            .await
+            .unwrap();
+        for (timestamp, payload) in [
+            (10, &b"hello"[..]),
+            (20, b"happy"),
+            (20, b"tax"),
+            (30, b"payer"),
+        ] {
+            multi_record_log
+                .append_record_with_timestamp("queue", None, timestamp, payload)
+                .await
+                .unwrap();
+        }
+        assert_eq!(multi_record_log.timestamp("queue", 0).unwrap(), None);
+        assert_eq!(multi_record_log.timestamp("queue", 1).unwrap(), Some(10));
+        assert_eq!(multi_record_log.timestamp("queue", 5).unwrap(), None);
+        multi_record_log.compact().await.unwrap();
+    }
+    let mut multi_record_log = MultiRecordLog::open_with_options(
+        tempdir.path(),
+        MultiRecordLogOptions::default().with_wall_clock_timestamps(true),
+    )
+    .await
+    .unwrap();
+    for (timestamp, expected_position) in [(0, 1), (10, 1), (11, 2), (20, 2), (30, 4), (31, 5)] {
+        assert_eq!(
+            multi_record_log
+                .position_for_timestamp("queue", timestamp)
+                .unwrap(),
+            expected_position
+        );
+    }
+    assert!(multi_record_log
+        .position_for_timestamp("missing", 0)
+        .is_err());
+    multi_record_log
+        .append_record("queue", None, b"now")
+        .await
+        .unwrap();
+    assert!(multi_record_log.timestamp("queue", 5).unwrap().unwrap() > 30);
+}
+
+#[tokio::test]
+#[cfg(all(feature = "lz4", feature = "zstd"))]
+async fn test_multi_record_log_compression() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let payloads: Vec<Vec<u8>> = (0..40u8)
+        .map(|i| {
+            format!("payload {i} ")
+                .repeat(100 * (i as usize % 5))
+                .into_bytes()
+        })
+        .collect();
+    let num_bytes: usize = payloads.iter().map(Vec::len).sum();
+    // The records appended with each compression remain readable with the others.
+    let compressions = [
+        Compression::Lz4,
+        Compression::Zstd(3),
+        Compression::None,
+        Compression::Lz4,
+    ];
+    for (i, compression) in compressions.iter().copied().enumerate() {
+        let options = MultiRecordLogOptions::default()
+            .with_compression(compression)
+            .with_spill_num_bytes(5_000);
+        let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+            .await
+            .unwrap();
+        if i == 0 {
+            multi_record_log.create_queue("queue").await.unwrap();
+        }
+        for (timestamp, payload) in (0..).zip(&payloads[i * 10..][..10]) {
+            multi_record_log
+                .append_record_with_timestamp("queue", None, timestamp, payload)
+                .await
+                .unwrap();
+        }
+        if i == 2 {
+            assert!(multi_record_log.disk_usage() < num_bytes as u64);
+            multi_record_log.compact().await.unwrap();
+        }
+        let num_records = (i + 1) * 10;
+        assert_eq!(
+            read_all_records_stream(&multi_record_log, "queue").await[..],
+            payloads[..num_records]
+        );
+        assert_eq!(
+            multi_record_log
+                .timestamp("queue", num_records as u64 - 1)
+                .unwrap(),
+            Some(9)
+        );
+    }
+}
+
+#[tokio::test]
+#[cfg(all(feature = "lz4", feature = "zstd"))]
+async fn test_multi_record_log_queue_compression() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let payload = b"hello ".repeat(100);
+    {
+        let options = MultiRecordLogOptions::default().with_compression(Compression::Lz4);
+        let mut multi_record_log = MultiRecordLog::open_with_options(tempdir.path(), options)
+            .await
+            .unwrap();
+        multi_record_log.set_queue_compression("zstd", Some(Compression::Zstd(3)));
+        multi_record_log.set_queue_compression("none", Some(Compression::None));
+        for queue in ["lz4", "zstd", "none"] {
+            multi_record_log.create_queue(queue).await.unwrap();
+            multi_record_log
+                .append_record(queue, None, &payload)
+                .await
+                .unwrap();
+        }
+        multi_record_log.set_queue_compression("zstd", None);
+        multi_record_log
+            .append_record("zstd", None, &payload)
+            .await
+            .unwrap();
+    }
+    let mut codecs: Vec<(String, u64, Codec)> = Vec::new();
+    let mut record_log_reader = RecordLogReader::open(tempdir.path()).await.unwrap();
+    while let Some((_, record)) = record_log_reader.read_record().await.unwrap() {
+        if let Record::AppendRecord {
+            queue,
+            position,
+            codec,
+            ..
+        } = record
+        {
+            codecs.push((queue.to_string(), position, codec));
+        }
+    }
+    assert_eq!(
+        codecs,
+        [
+            ("lz4".to_string(), 0, Codec::Lz4),
+            ("zstd".to_string(), 0, Codec::Zstd),
+            ("none".to_string(), 0, Codec::None),
+            ("zstd".to_string(), 1, Codec::Lz4),
+        ]
+    );
+    drop(record_log_reader);
+    let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    for queue in ["lz4", "zstd", "none"] {
+        assert_eq!(read_all_records(&multi_record_log, queue)[0], payload);
+    }
+}
+
+#[tokio::test]
+async fn test_multi_record_log_directory_locked() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let mut multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    multi_record_log.create_queue("queue").await.unwrap();
+    multi_record_log
+        .append_record("queue", None, b"hello")
+        .await
+        .unwrap();
+    // The directory is locked as long as the log is opened.
+    let lock_error = MultiRecordLog::open(tempdir.path()).await.err().unwrap();
+    if let ReadRecordError::IoError(io_error) = &lock_error {
+        assert_eq!(io_error.kind(), std::io::ErrorKind::WouldBlock);
+    } else {
+        panic!("expected an io error, got {:?}", lock_error);
+    }
+    drop(multi_record_log);
+    let multi_record_log = MultiRecordLog::open(tempdir.path()).await.unwrap();
+    assert_eq!(read_all_records(&multi_record_log, "queue"), [b"hello"]);
+}
+
+#[tokio::test]
+async fn test_multi_record_log_open_with_storage() {
+    let tempdir = tempfile::tempdir().unwrap();
+    {
+        let storage = LocalStorage::open(tempdir.path()).await.unwrap();
+        let mut multi_record_log =
+            MultiRecordLog::open_with_storage(Arc::new(storage), MultiRecordLogOptions::default())
+                .await
+                .unwrap();
+        multi_record_log.create_queue("queue").await.unwrap();
+        multi_record_log
+            .append_record("queue", None, b"hello")
+            .await
+            .unwrap();
+        // The storage stays locked while the log is opened.
+        let lock_error = MultiRecordLog::open(tempdir.path()).await.err().unwrap();
+        assert!(matches!(lock_error, ReadRecordError::IoError(_)));
+    }
+    {
+        let storage = LocalStorage::open(tempdir.path()).await.unwrap();
+        let mut file_names: Vec<String> = storage
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(file_name, _)| file_name)
+            .collect();
+        file_names.sort();
+        assert_eq!(file_names, ["lock", "wal-00000000000000000001"]);
+        let multi_record_log =
+            MultiRecordLog::open_with_storage(Arc::new(storage), MultiRecordLogOptions::default())
+                .await
+                .unwrap();
+        assert_eq!(read_all_records(&multi_record_log, "queue"), [b"hello"]);
+    }
+}
+
+#[tokio::test]
+async fn test_multi_record_log_memory_storage() {
+    let storage = MemoryStorage::default();
+    let options = MultiRecordLogOptions::default()
+        .with_rollover_num_bytes(10_000)
+        .with_spill_num_bytes(5_000);
+    let payloads: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i; 1_000]).collect();
+    {
+        let mut multi_record_log =
+            MultiRecordLog::open_with_storage(Arc::new(storage.clone()), options)
+                .await
+                .unwrap();
+        multi_record_log.create_queue("queue").await.unwrap();
+        for payload in &payloads {
+            multi_record_log
+                .append_record("queue", None, payload)
+                .await
+                .unwrap();
+        }
+        assert!(multi_record_log.num_files() > 1);
+        assert_eq!(
+            read_all_records_stream(&multi_record_log, "queue").await,
+            payloads
+        );
+        multi_record_log.checkpoint().await.unwrap();
+        multi_record_log.truncate("queue", 9).await.unwrap();
+    }
+    // The log is reopened from a clone of the storage it was written to.
+    let multi_record_log = MultiRecordLog::open_with_storage(Arc::new(storage), options)
+        .await
+        .unwrap();
+    let records: Vec<Vec<u8>> = multi_record_log
+        .range_stream("queue", ..)
+        .unwrap()
+        .map_ok(|(_, payload)| payload.into_owned())
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(records, payloads[10..]);
+}
+
+#[tokio::test]
+async fn test_multi_record_log_spilled_record_altered() {
+    let storage = MemoryStorage::default();
+    let options = MultiRecordLogOptions::default().with_spill_num_bytes(5_000);
+    let mut multi_record_log =
+        MultiRecordLog::open_with_storage(Arc::new(storage.clone()), options)
+            .await
+            .unwrap();
+    multi_record_log.create_queue("queue").await.unwrap();
+    for i in 0..10u8 {
+        multi_record_log
+            .append_record("queue", None, &[i; 1_000])
+            .await
+            .unwrap();
+    }
+    // Overwrites the first record appended, which is spilled.
+    let (file_name, _) = storage.list().await.unwrap().into_iter().next().unwrap();
+    let mut file = storage.open(&file_name).await.unwrap();
+    file.seek(std::io::SeekFrom::Start(100)).await.unwrap();
+    file.write_all(&[255u8; 100]).await.unwrap();
+    let records: Vec<_> = multi_record_log
+        .range_stream("queue", ..)
+        .unwrap()
+        .collect()
+        .await;
+    assert_eq!(records.len(), 10);
+    assert!(matches!(records[0], Err(ReadRecordError::Corruption)));
+    for (position, record_res) in (1u64..).zip(&records[1..]) {
+        let (record_position, payload) = record_res.as_ref().unwrap();
+        assert_eq!(*record_position, position);
+        assert_eq!(&payload[..], &[position as u8; 1_000][..]);
+    }
+    let shared_log = SharedMultiRecordLog::new(multi_record_log);
+    assert!(matches!(
+        shared_log.range("queue", ..).await,
+        Err(RangeError::ReadError(ReadRecordError::Corruption))
+    ));
+    assert_eq!(shared_log.range("queue", 1..).await.unwrap().len(), 9);
+}
+
+// Appends the payloads until an append fails, and returns the payloads appended.
+async fn append_until_error(
+    multi_record_log: &mut MultiRecordLog,
+    payloads: &[Vec<u8>],
+) -> Vec<Vec<u8>> {
+    let mut appended_payloads = Vec::new();
+    for payload in payloads {
+        if multi_record_log
+            .append_record("queue", None, payload)
+            .await
+            .is_err()
+        {
+            break;
+        }
+        appended_payloads.push(payload.clone());
+    }
+    appended_payloads
+}
+
+#[tokio::test]
+async fn test_multi_record_log_crash_after_torn_write() {
+    // Some of the records span several blocks, or leave less than a header at the end of
+    // a block.
+    let payloads: Vec<Vec<u8>> = [10, 40_000, 7, 25_490, 32_000, 100]
+        .iter()
+        .enumerate()
+        .map(|(i, len)| vec![i as u8; *len])
+        .collect();
+    for fault_num_bytes in [0, 1, 6, 7, 20, 32_760, 32_768, 32_775, 65_540, 90_000] {
+        for num_unsynced_bytes_kept in [0, 3, 10_000, u64::MAX] {
+            let storage = FaultyStorage::new(MemoryStorage::default());
+            let mut multi_record_log = MultiRecordLog::open_with_storage(
+                Arc::new(storage.clone()),
+                MultiRecordLogOptions::default(),
+            )
+            .await
+            .unwrap();
+            multi_record_log.create_queue("queue").await.unwrap();
+            storage.inject_fault(Fault::IoError, fault_num_bytes);
+            let mut appended_payloads = append_until_error(&mut multi_record_log, &payloads).await;
+            drop(multi_record_log);
+            storage.crash(num_unsynced_bytes_kept).await.unwrap();
+            {
+                let mut multi_record_log = MultiRecordLog::open_with_storage(
+                    Arc::new(storage.clone()),
+                    MultiRecordLogOptions::default(),
+                )
+                .await
+                .unwrap();
+                assert_eq!(
+                    read_all_records(&multi_record_log, "queue"),
+                    appended_payloads,
+                    "fault after {fault_num_bytes} bytes, {num_unsynced_bytes_kept} unsynced \
+                     bytes kept"
+                );
+                multi_record_log
+                    .append_record("queue", None, b"hello")
+                    .await
+                    .unwrap();
+                appended_payloads.push(b"hello".to_vec());
+            }
+            let multi_record_log = MultiRecordLog::open_with_storage(
+                Arc::new(storage),
+                MultiRecordLogOptions::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                read_all_records(&multi_record_log, "queue"),
+                appended_payloads
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_multi_record_log_no_space() {
+    let payloads: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; 10_000]).collect();
+    let storage = FaultyStorage::new(MemoryStorage::default());
+    let mut multi_record_log = MultiRecordLog::open_with_storage(
+        Arc::new(storage.clone()),
+        MultiRecordLogOptions::default(),
+    )
+    .await
+    .unwrap();
+    multi_record_log.create_queue("queue").await.unwrap();
+    storage.inject_fault(Fault::NoSpace, 45_000);
+    let appended_payloads = append_until_error(&mut multi_record_log, &payloads).await;
+    assert_eq!(appended_payloads.len(), 4);
+    assert!(multi_record_log.is_poisoned());
+    drop(multi_record_log);
+    // Once space is freed, the log is reopened without the record partially written.
+    storage.heal();
+    let mut multi_record_log =
+        MultiRecordLog::open_with_storage(Arc::new(storage), MultiRecordLogOptions::default())
+            .await
+            .unwrap();
+    assert_eq!(
+        read_all_records(&multi_record_log, "queue"),
+        appended_payloads
+    );
+    multi_record_log
+        .append_record("queue", None, b"hello")
+        .await
+        .unwrap();
+}
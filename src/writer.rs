@@ -0,0 +1,804 @@
+use std::collections::HashMap;
+use std::io;
+use std::ops::{Range, RangeTo};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use tracing::warn;
+
+use crate::checkpoint::serialize_checkpoint;
+use crate::compression::{Codec, Compression};
+use crate::error::{
+    AppendError, CheckpointError, CompactError, CreateQueueError, DeleteQueueError, ExceededLimit,
+    MissingQueue, Poisoned, TruncateError,
+};
+use crate::mem::{self, MemQueue, MemQueues, Payload, Truncation};
+use crate::position::FileNumber;
+use crate::rolling::{Record, RecordLogWriter};
+
+/// Gives access to the in-memory queues updated by a `MultiRecordLogWriter`.
+///
+/// The queues are either owned by the `MultiRecordLog`, or shared
+/// behind a lock with concurrent readers.
+pub(crate) trait MemQueuesAccess {
+    fn with_mem_queues<T>(&self, read_fn: impl FnOnce(&MemQueues) -> T) -> T;
+
+    fn with_mem_queues_mut<T>(&mut self, write_fn: impl FnOnce(&mut MemQueues) -> T) -> T;
+}
+
+impl MemQueuesAccess for MemQueues {
+    fn with_mem_queues<T>(&self, read_fn: impl FnOnce(&MemQueues) -> T) -> T {
+        read_fn(self)
+    }
+
+    fn with_mem_queues_mut<T>(&mut self, write_fn: impl FnOnce(&mut MemQueues) -> T) -> T {
+        write_fn(self)
+    }
+}
+
+impl MemQueuesAccess for Arc<RwLock<MemQueues>> {
+    fn with_mem_queues<T>(&self, read_fn: impl FnOnce(&MemQueues) -> T) -> T {
+        let mem_queues = self
+            .read()
+            .expect("the mem queues lock should not be poisoned");
+        read_fn(&mem_queues)
+    }
+
+    fn with_mem_queues_mut<T>(&mut self, write_fn: impl FnOnce(&mut MemQueues) -> T) -> T {
+        let mut mem_queues = self
+            .write()
+            .expect("the mem queues lock should not be poisoned");
+        write_fn(&mut mem_queues)
+    }
+}
+
+/// Limits beyond which appends are rejected with `AppendError::Full`.
+///
+/// All of the limits are disabled by default.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CapacityLimits {
+    /// Maximum number of bytes of the records held in memory, across all queues.
+    pub max_memory_num_bytes: Option<usize>,
+    /// Maximum number of bytes of the records held by a single queue.
+    pub max_queue_num_bytes: Option<usize>,
+    /// Maximum number of files of the log on disk.
+    pub max_num_files: Option<usize>,
+    /// Maximum number of bytes of the log files and of the checkpoint on disk.
+    pub max_disk_usage: Option<u64>,
+}
+
+struct UnflushedRecord {
+    queue: String,
+    file_number: FileNumber,
+    offset: u64,
+    position: u64,
+    timestamp_opt: Option<u64>,
+    payload: Vec<u8>,
+}
+
+/// Write half of a `MultiRecordLog`.
+///
+/// It writes the records to the record log, and applies them to the in-memory
+/// queues once they are flushed. The in-memory queues are passed to each method,
+/// so that they can be read concurrently with a write, if they are shared.
+pub(crate) struct MultiRecordLogWriter {
+    record_log_writer: RecordLogWriter,
+    // Records written to the record log but not flushed yet.
+    // They are only added to the in-memory queues once flushed.
+    unflushed_records: Vec<UnflushedRecord>,
+    // Set when an IO error leaves the record log in an unknown state.
+    // The in-memory queues cannot be trusted to match the files anymore.
+    poisoned: bool,
+    capacity_limits: CapacityLimits,
+    checkpoint_interval: Option<Duration>,
+    last_checkpoint: Instant,
+    // Position of the record log at the time of the last checkpoint.
+    last_checkpoint_position: Option<(FileNumber, u64)>,
+    // If true, records appended without timestamp are timestamped with the wall clock.
+    wall_clock_timestamps: bool,
+    // Compression of the payloads appended.
+    compression: Compression,
+    // Compression of the payloads appended to these queues, overriding `compression`.
+    queue_compressions: HashMap<String, Compression>,
+    // Buffer the payloads are compressed into before being written.
+    compression_buffer: Vec<u8>,
+}
+
+impl MultiRecordLogWriter {
+    pub fn new(record_log_writer: RecordLogWriter) -> Self {
+        MultiRecordLogWriter {
+            record_log_writer,
+            unflushed_records: Vec::new(),
+            poisoned: false,
+            capacity_limits: CapacityLimits::default(),
+            checkpoint_interval: None,
+            last_checkpoint: Instant::now(),
+            last_checkpoint_position: None,
+            wall_clock_timestamps: false,
+            compression: Compression::None,
+            queue_compressions: HashMap::new(),
+            compression_buffer: Vec::new(),
+        }
+    }
+
+    pub fn set_capacity_limits(&mut self, capacity_limits: CapacityLimits) {
+        self.capacity_limits = capacity_limits;
+    }
+
+    pub fn set_checkpoint_interval(&mut self, checkpoint_interval: Option<Duration>) {
+        self.checkpoint_interval = checkpoint_interval;
+    }
+
+    pub fn set_wall_clock_timestamps(&mut self, wall_clock_timestamps: bool) {
+        self.wall_clock_timestamps = wall_clock_timestamps;
+    }
+
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    pub fn set_queue_compression(&mut self, queue: &str, compression_opt: Option<Compression>) {
+        if let Some(compression) = compression_opt {
+            self.queue_compressions
+                .insert(queue.to_string(), compression);
+        } else {
+            self.queue_compressions.remove(queue);
+        }
+    }
+
+    /// Returns the timestamp of a record appended with `timestamp_opt`.
+    fn record_timestamp(&self, timestamp_opt: Option<u64>) -> Option<u64> {
+        if timestamp_opt.is_some() || !self.wall_clock_timestamps {
+            return timestamp_opt;
+        }
+        let now_millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+        Some(now_millis)
+    }
+
+    /// Checks that appending `num_bytes` to `queue` does not exceed the capacity limits,
+    /// taking in account the records written but not flushed yet.
+    fn check_capacity(
+        &self,
+        in_mem_queues: &impl MemQueuesAccess,
+        queue: &str,
+        num_bytes: usize,
+    ) -> Result<(), AppendError> {
+        let capacity_limits = self.capacity_limits;
+        if let Some(max_num_files) = capacity_limits.max_num_files {
+            let num_files = self.record_log_writer.num_files()
+                + self.record_log_writer.need_new_file() as usize;
+            if num_files > max_num_files {
+                return Err(AppendError::Full {
+                    limit: ExceededLimit::NumFiles,
+                });
+            }
+        }
+        if let Some(max_disk_usage) = capacity_limits.max_disk_usage {
+            if self.disk_usage() + num_bytes as u64 > max_disk_usage {
+                return Err(AppendError::Full {
+                    limit: ExceededLimit::DiskUsage,
+                });
+            }
+        }
+        if let Some(max_memory_num_bytes) = capacity_limits.max_memory_num_bytes {
+            let unflushed_num_bytes: usize = self
+                .unflushed_records
+                .iter()
+                .map(|unflushed_record| unflushed_record.payload.len())
+                .sum();
+            let memory_num_bytes =
+                in_mem_queues.with_mem_queues(|in_mem_queues| in_mem_queues.num_bytes());
+            if memory_num_bytes + unflushed_num_bytes + num_bytes > max_memory_num_bytes {
+                return Err(AppendError::Full {
+                    limit: ExceededLimit::MemoryNumBytes,
+                });
+            }
+        }
+        if let Some(max_queue_num_bytes) = capacity_limits.max_queue_num_bytes {
+            let unflushed_num_bytes: usize = self
+                .unflushed_records
+                .iter()
+                .filter(|unflushed_record| unflushed_record.queue == queue)
+                .map(|unflushed_record| unflushed_record.payload.len())
+                .sum();
+            let queue_num_bytes = in_mem_queues
+                .with_mem_queues(|in_mem_queues| in_mem_queues.queue_stats(queue))?
+                .num_bytes;
+            if queue_num_bytes + unflushed_num_bytes + num_bytes > max_queue_num_bytes {
+                return Err(AppendError::Full {
+                    limit: ExceededLimit::QueueNumBytes,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn num_files(&self) -> usize {
+        self.record_log_writer.num_files()
+    }
+
+    pub fn disk_usage(&self) -> u64 {
+        self.record_log_writer.disk_usage()
+    }
+
+    fn check_not_poisoned(&self) -> Result<(), Poisoned> {
+        if self.poisoned {
+            return Err(Poisoned);
+        }
+        Ok(())
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    // The following methods wrap the record log writer, and poison the log
+    // if they fail.
+
+    async fn roll_if_needed(&mut self) -> io::Result<FileNumber> {
+        let roll_res = self.record_log_writer.roll_if_needed().await;
+        self.poisoned |= roll_res.is_err();
+        roll_res
+    }
+
+    /// Returns the offset the next record will be written at, in the active file.
+    fn next_record_offset(&self) -> u64 {
+        let (_, offset) = self
+            .record_log_writer
+            .position()
+            .expect("roll_if_needed should have been called before");
+        offset
+    }
+
+    async fn write_record(&mut self, record: Record<'_>) -> io::Result<()> {
+        let write_res = self.record_log_writer.write_record(record).await;
+        self.poisoned |= write_res.is_err();
+        write_res
+    }
+
+    async fn sync(&mut self) -> io::Result<()> {
+        let sync_res = self.record_log_writer.sync().await;
+        self.poisoned |= sync_res.is_err();
+        sync_res
+    }
+
+    /// Flushes the records written so far, and syncs them according to the sync policy.
+    ///
+    /// Once flushed, the records written without flush are added to the in-memory queues.
+    pub async fn flush(
+        &mut self,
+        in_mem_queues: &mut impl MemQueuesAccess,
+    ) -> Result<(), AppendError> {
+        self.check_not_poisoned()?;
+        self.flush_records(in_mem_queues).await?;
+        Ok(())
+    }
+
+    async fn flush_records(&mut self, in_mem_queues: &mut impl MemQueuesAccess) -> io::Result<()> {
+        if let Err(io_error) = self.record_log_writer.flush().await {
+            self.poisoned = true;
+            self.unflushed_records.clear();
+            return Err(io_error);
+        }
+        if self.unflushed_records.is_empty() {
+            return Ok(());
+        }
+        let unflushed_records = &mut self.unflushed_records;
+        in_mem_queues.with_mem_queues_mut(|in_mem_queues| {
+            for unflushed_record in unflushed_records.drain(..) {
+                in_mem_queues
+                    .append_record(
+                        &unflushed_record.queue,
+                        unflushed_record.file_number,
+                        unflushed_record.offset,
+                        Some(unflushed_record.position),
+                        unflushed_record.timestamp_opt,
+                        &unflushed_record.payload,
+                    )
+                    .expect(
+                        "the position of the record should have been checked before writing it",
+                    );
+            }
+        });
+        Ok(())
+    }
+
+    /// Flushes the records written so far, and rolls the log over to a new file.
+    #[cfg(any(test, feature = "simulation"))]
+    pub async fn rollover(
+        &mut self,
+        in_mem_queues: &mut impl MemQueuesAccess,
+    ) -> Result<(), AppendError> {
+        self.check_not_poisoned()?;
+        self.flush_records(in_mem_queues).await?;
+        let rollover_res = self.record_log_writer.rollover().await;
+        self.poisoned |= rollover_res.is_err();
+        rollover_res?;
+        Ok(())
+    }
+
+    pub async fn create_queue(
+        &mut self,
+        in_mem_queues: &mut impl MemQueuesAccess,
+        queue: &str,
+    ) -> Result<(), CreateQueueError> {
+        self.check_not_poisoned()?;
+        if in_mem_queues.with_mem_queues(|in_mem_queues| in_mem_queues.contains_queue(queue)) {
+            return Err(CreateQueueError::AlreadyExists);
+        }
+        self.roll_if_needed().await?;
+        let record = Record::Touch { queue, position: 0 };
+        self.write_record(record).await?;
+        self.flush_records(in_mem_queues).await?;
+        in_mem_queues.with_mem_queues_mut(|in_mem_queues| in_mem_queues.create_queue(queue))?;
+        Ok(())
+    }
+
+    fn last_unflushed_position(&self, queue: &str) -> Option<u64> {
+        self.unflushed_records
+            .iter()
+            .rev()
+            .find(|unflushed_record| unflushed_record.queue == queue)
+            .map(|unflushed_record| unflushed_record.position)
+    }
+
+    /// Returns the position a record should be appended at, taking in account the records
+    /// written but not flushed yet.
+    fn position_for_append(
+        &self,
+        in_mem_queues: &impl MemQueuesAccess,
+        queue: &str,
+        position_opt: Option<u64>,
+    ) -> Result<Option<u64>, AppendError> {
+        if let Some(last_unflushed_position) = self.last_unflushed_position(queue) {
+            mem::position_for_append(last_unflushed_position + 1, position_opt)
+        } else {
+            in_mem_queues.with_mem_queues(|in_mem_queues| {
+                in_mem_queues.position_for_append(queue, position_opt)
+            })
+        }
+    }
+
+    /// Returns the position of the next record appended to `queue`, taking in account
+    /// the records written but not flushed yet.
+    fn next_position(
+        &self,
+        in_mem_queues: &impl MemQueuesAccess,
+        queue: &str,
+    ) -> Result<u64, MissingQueue> {
+        if let Some(last_unflushed_position) = self.last_unflushed_position(queue) {
+            Ok(last_unflushed_position + 1)
+        } else {
+            in_mem_queues.with_mem_queues(|in_mem_queues| in_mem_queues.next_position(queue))
+        }
+    }
+
+    /// Writes the record to the log, without flushing it, nor adding it to the in-memory queue.
+    ///
+    /// Returns the file number, the offset and the position of the record, or None if
+    /// the record was already appended.
+    ///
+    /// `check_capacity` is false if the capacity limits were already checked, for a batch.
+    async fn write_append_record(
+        &mut self,
+        in_mem_queues: &impl MemQueuesAccess,
+        queue: &str,
+        position_opt: Option<u64>,
+        timestamp_opt: Option<u64>,
+        payload: &[u8],
+        check_capacity: bool,
+    ) -> Result<Option<(FileNumber, u64, u64)>, AppendError> {
+        self.check_not_poisoned()?;
+        let position =
+            if let Some(position) = self.position_for_append(in_mem_queues, queue, position_opt)? {
+                position
+            } else {
+                return Ok(None);
+            };
+        if check_capacity {
+            self.check_capacity(in_mem_queues, queue, payload.len())?;
+        }
+        let file_number = self.roll_if_needed().await?;
+        let offset = self.next_record_offset();
+        self.write_append_record_payload(position, queue, timestamp_opt, payload)
+            .await?;
+        Ok(Some((file_number, offset, position)))
+    }
+
+    /// Writes an `AppendRecord`, its payload compressed as configured.
+    async fn write_append_record_payload(
+        &mut self,
+        position: u64,
+        queue: &str,
+        timestamp_opt: Option<u64>,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let mut compression_buffer = std::mem::take(&mut self.compression_buffer);
+        let compression = self
+            .queue_compressions
+            .get(queue)
+            .unwrap_or(&self.compression);
+        let codec = compression.compress(payload, &mut compression_buffer);
+        let record = Record::AppendRecord {
+            position,
+            queue,
+            timestamp: timestamp_opt,
+            codec,
+            payload: if codec == Codec::None {
+                payload
+            } else {
+                &compression_buffer
+            },
+        };
+        let write_res = self.write_record(record).await;
+        self.compression_buffer = compression_buffer;
+        write_res
+    }
+
+    /// Appends a record, timestamped with `timestamp_opt`, or with the wall clock
+    /// if enabled.
+    pub async fn append_record(
+        &mut self,
+        in_mem_queues: &mut impl MemQueuesAccess,
+        queue: &str,
+        position_opt: Option<u64>,
+        timestamp_opt: Option<u64>,
+        payload: &[u8],
+    ) -> Result<Option<u64>, AppendError> {
+        let timestamp_opt = self.record_timestamp(timestamp_opt);
+        let (file_number, offset, position) = if let Some(written) = self
+            .write_append_record(
+                in_mem_queues,
+                queue,
+                position_opt,
+                timestamp_opt,
+                payload,
+                true,
+            )
+            .await?
+        {
+            written
+        } else {
+            return Ok(None);
+        };
+        self.flush(in_mem_queues).await?;
+        let append_res = in_mem_queues.with_mem_queues_mut(|in_mem_queues| {
+            in_mem_queues.append_record(
+                queue,
+                file_number,
+                offset,
+                Some(position),
+                timestamp_opt,
+                payload,
+            )
+        });
+        self.checkpoint_if_needed(in_mem_queues).await;
+        append_res
+    }
+
+    /// Appends a record to the log, without flushing it.
+    ///
+    /// The record is only written to the file (and possibly synced) on the next `flush`,
+    /// and only then added to the in-memory queue.
+    /// This makes it possible to commit several records at once.
+    pub async fn append_record_without_flush(
+        &mut self,
+        in_mem_queues: &impl MemQueuesAccess,
+        queue: &str,
+        position_opt: Option<u64>,
+        payload: &[u8],
+    ) -> Result<Option<u64>, AppendError> {
+        self.write_unflushed_record(in_mem_queues, queue, position_opt, payload, true)
+            .await
+    }
+
+    async fn write_unflushed_record(
+        &mut self,
+        in_mem_queues: &impl MemQueuesAccess,
+        queue: &str,
+        position_opt: Option<u64>,
+        payload: &[u8],
+        check_capacity: bool,
+    ) -> Result<Option<u64>, AppendError> {
+        let timestamp_opt = self.record_timestamp(None);
+        let (file_number, offset, position) = if let Some(written) = self
+            .write_append_record(
+                in_mem_queues,
+                queue,
+                position_opt,
+                timestamp_opt,
+                payload,
+                check_capacity,
+            )
+            .await?
+        {
+            written
+        } else {
+            return Ok(None);
+        };
+        self.unflushed_records.push(UnflushedRecord {
+            queue: queue.to_string(),
+            file_number,
+            offset,
+            position,
+            timestamp_opt,
+            payload: payload.to_vec(),
+        });
+        Ok(Some(position))
+    }
+
+    pub async fn append_records<'a>(
+        &mut self,
+        in_mem_queues: &mut impl MemQueuesAccess,
+        queue: &str,
+        position_opt: Option<u64>,
+        payloads: impl Iterator<Item = &'a [u8]>,
+    ) -> Result<Option<Range<u64>>, AppendError> {
+        let append_records_res = self
+            .append_records_without_flush(in_mem_queues, queue, position_opt, payloads)
+            .await?;
+        self.flush(in_mem_queues).await?;
+        self.checkpoint_if_needed(in_mem_queues).await;
+        Ok(append_records_res)
+    }
+
+    /// Appends a batch of records to the log, without flushing it.
+    ///
+    /// The capacity limits are checked for the batch as a whole, so that
+    /// it is either entirely appended or rejected.
+    ///
+    /// See `MultiRecordLog::append_records` and `append_record_without_flush`.
+    pub async fn append_records_without_flush<'a>(
+        &mut self,
+        in_mem_queues: &impl MemQueuesAccess,
+        queue: &str,
+        position_opt: Option<u64>,
+        payloads: impl Iterator<Item = &'a [u8]>,
+    ) -> Result<Option<Range<u64>>, AppendError> {
+        let payloads: Vec<&[u8]> = payloads.collect();
+        if payloads.is_empty() {
+            return Ok(None);
+        }
+        let num_records = payloads.len() as u64;
+        if let Some(position) = position_opt {
+            let next_position = self.next_position(in_mem_queues, queue)?;
+            if position < next_position {
+                if position + num_records == next_position {
+                    return Ok(None);
+                }
+                return Err(AppendError::Past);
+            }
+        }
+        let num_bytes = payloads.iter().map(|payload| payload.len()).sum();
+        self.check_capacity(in_mem_queues, queue, num_bytes)?;
+        let mut position_opt = position_opt;
+        let mut first_position_opt = None;
+        for payload in payloads {
+            let appended_position = self
+                .write_unflushed_record(in_mem_queues, queue, position_opt, payload, false)
+                .await?
+                .expect("the record should not have been appended already");
+            first_position_opt.get_or_insert(appended_position);
+            position_opt = None;
+        }
+        Ok(first_position_opt.map(|first_position| first_position..first_position + num_records))
+    }
+
+    async fn log_positions(&mut self, in_mem_queues: &mut impl MemQueuesAccess) -> io::Result<()> {
+        let empty_queue_positions: Vec<(String, u64)> =
+            in_mem_queues.with_mem_queues(|in_mem_queues| {
+                in_mem_queues
+                    .empty_queue_positions()
+                    .map(|(queue, position)| (queue.to_string(), position))
+                    .collect()
+            });
+        for (queue, position) in &empty_queue_positions {
+            let record = Record::Touch {
+                queue,
+                position: *position,
+            };
+            self.write_record(record).await?;
+        }
+        self.flush_records(in_mem_queues).await?;
+        Ok(())
+    }
+
+    pub async fn truncate(
+        &mut self,
+        in_mem_queues: &mut impl MemQueuesAccess,
+        queue: &str,
+        position: u64,
+    ) -> Result<(), TruncateError> {
+        self.check_not_poisoned()?;
+        if !in_mem_queues.with_mem_queues(|in_mem_queues| in_mem_queues.contains_queue(queue)) {
+            return Err(TruncateError::MissingQueue(queue.to_string()));
+        }
+        let file_number = self.roll_if_needed().await?;
+        self.write_record(Record::Truncate { position, queue })
+            .await?;
+        self.flush_records(in_mem_queues).await?;
+        let truncation = in_mem_queues
+            .with_mem_queues_mut(|in_mem_queues| in_mem_queues.truncate(queue, position));
+        self.remove_files(in_mem_queues, file_number, truncation)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_queue(
+        &mut self,
+        in_mem_queues: &mut impl MemQueuesAccess,
+        queue: &str,
+    ) -> Result<(), DeleteQueueError> {
+        self.check_not_poisoned()?;
+        if !in_mem_queues.with_mem_queues(|in_mem_queues| in_mem_queues.contains_queue(queue)) {
+            return Err(DeleteQueueError::MissingQueue(queue.to_string()));
+        }
+        let file_number = self.roll_if_needed().await?;
+        self.write_record(Record::DeleteQueue { queue }).await?;
+        self.flush_records(in_mem_queues).await?;
+        let truncation =
+            in_mem_queues.with_mem_queues_mut(|in_mem_queues| in_mem_queues.delete_queue(queue))?;
+        self.remove_files(in_mem_queues, file_number, truncation)
+            .await?;
+        Ok(())
+    }
+
+    /// Rewrites the records of the queues retaining files older than the active file,
+    /// and removes these files.
+    ///
+    /// Each queue is rewritten as a whole, between a `CompactionStart` and a `CompactionEnd`
+    /// record, so that an interrupted compaction can be ignored upon replay. The files are
+    /// only removed once the rewritten records are synced.
+    pub async fn compact(
+        &mut self,
+        in_mem_queues: &mut impl MemQueuesAccess,
+    ) -> Result<(), CompactError> {
+        self.check_not_poisoned()?;
+        self.flush_records(in_mem_queues).await?;
+        let compaction_file_number = self.roll_if_needed().await?;
+        let queues = in_mem_queues.with_mem_queues(|in_mem_queues| {
+            in_mem_queues.queues_retaining_files_before(compaction_file_number)
+        });
+        if queues.is_empty() {
+            return Ok(());
+        }
+        let mut compacted_queues: Vec<(String, MemQueue)> = Vec::with_capacity(queues.len());
+        let mut payload_reader =
+            in_mem_queues.with_mem_queues(|in_mem_queues| in_mem_queues.payload_reader());
+        for queue in queues {
+            let (start_position, payloads): (u64, Vec<(u64, Option<u64>, Payload)>) = in_mem_queues
+                .with_mem_queues(|in_mem_queues| {
+                    let start_position = in_mem_queues.start_position(&queue)?;
+                    let mut payloads = Vec::new();
+                    for (position, payload) in in_mem_queues.range_payloads(&queue, ..)? {
+                        let timestamp_opt = in_mem_queues.timestamp(&queue, position)?;
+                        payloads.push((position, timestamp_opt, payload));
+                    }
+                    Ok::<_, MissingQueue>((start_position, payloads))
+                })
+                .expect("the queue should exist");
+            // The records spilled are read back before the compaction of the queue
+            // is written, so that failing to read them does not leave it half-written.
+            let mut records: Vec<(u64, Option<u64>, Bytes)> = Vec::with_capacity(payloads.len());
+            for (position, timestamp_opt, payload) in payloads {
+                let payload = payload.read(position, &mut payload_reader).await?;
+                records.push((position, timestamp_opt, payload));
+            }
+            // All of the records of a queue are written to the same file.
+            let file_number = self.roll_if_needed().await?;
+            self.write_record(Record::CompactionStart {
+                position: start_position,
+                queue: &queue,
+            })
+            .await?;
+            let mut mem_queue = MemQueue::with_next_position(start_position);
+            for (position, timestamp_opt, payload) in &records {
+                let offset = self.next_record_offset();
+                self.write_append_record_payload(*position, &queue, *timestamp_opt, payload)
+                    .await?;
+                mem_queue
+                    .append_record(
+                        file_number,
+                        offset,
+                        Some(*position),
+                        *timestamp_opt,
+                        payload,
+                    )
+                    .expect("the records of the queue should be contiguous");
+            }
+            self.write_record(Record::CompactionEnd { queue: &queue })
+                .await?;
+            compacted_queues.push((queue, mem_queue));
+        }
+        self.sync().await?;
+        in_mem_queues.with_mem_queues_mut(|in_mem_queues| {
+            for (queue, mem_queue) in compacted_queues {
+                in_mem_queues.replace_queue(&queue, mem_queue);
+            }
+        });
+        // The records of all of the queues are now in the compaction file, or more recent ones.
+        self.remove_files(
+            in_mem_queues,
+            compaction_file_number,
+            Truncation::RemoveAllFiles,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Writes a checkpoint of the in-memory queues, as of the current position of the log.
+    ///
+    /// The log is synced first, so that the checkpoint never refers to records
+    /// that could be lost.
+    pub async fn checkpoint(
+        &mut self,
+        in_mem_queues: &mut impl MemQueuesAccess,
+    ) -> Result<(), CheckpointError> {
+        self.check_not_poisoned()?;
+        self.flush_records(in_mem_queues).await?;
+        let position_opt = self.record_log_writer.position();
+        if position_opt == self.last_checkpoint_position {
+            // Nothing was written since the last checkpoint.
+            return Ok(());
+        }
+        let (file_number, offset) = position_opt.expect("a file should have been written to");
+        self.sync().await?;
+        let checkpoint = in_mem_queues.with_mem_queues(|in_mem_queues| {
+            serialize_checkpoint(file_number, offset, in_mem_queues)
+        });
+        self.record_log_writer.write_checkpoint(&checkpoint).await?;
+        self.last_checkpoint = Instant::now();
+        self.last_checkpoint_position = position_opt;
+        Ok(())
+    }
+
+    /// Writes a checkpoint if the checkpoint interval elapsed since the last one.
+    ///
+    /// This is best effort, as the records appended before are already committed: a failure
+    /// is logged, the previous checkpoint is kept, and a new checkpoint is attempted on the
+    /// next call. However, if the log fails to be synced before writing the checkpoint,
+    /// the log is poisoned, and the next operations fail with `Poisoned`.
+    pub async fn checkpoint_if_needed(&mut self, in_mem_queues: &mut impl MemQueuesAccess) {
+        match self.checkpoint_interval {
+            Some(checkpoint_interval) if self.last_checkpoint.elapsed() >= checkpoint_interval => {
+                if let Err(checkpoint_error) = self.checkpoint(in_mem_queues).await {
+                    warn!(
+                        error=%checkpoint_error,
+                        poisoned=self.poisoned,
+                        "failed to write the periodic checkpoint"
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Removes the files that are not retained by any queue anymore, after `truncation`.
+    ///
+    /// `file_number` is the file the truncation was written to. It is never removed.
+    async fn remove_files(
+        &mut self,
+        in_mem_queues: &mut impl MemQueuesAccess,
+        file_number: FileNumber,
+        truncation: Truncation,
+    ) -> io::Result<()> {
+        // Queues that end up empty need their position to be logged again,
+        // so that it is not lost when the files are removed.
+        self.log_positions(in_mem_queues).await?;
+        let files_to_remove: RangeTo<FileNumber> = match truncation {
+            Truncation::NoTruncation => {
+                return Ok(());
+            }
+            Truncation::RemoveFiles(files_to_remove) => ..files_to_remove.end.min(file_number),
+            Truncation::RemoveAllFiles => ..file_number,
+        };
+
+        self.record_log_writer.truncate(files_to_remove).await?;
+        Ok(())
+    }
+}